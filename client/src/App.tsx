@@ -77,6 +77,15 @@ interface AfkVillage {
   days_without_growth: number;
 }
 
+// The server's maximum page size for /api/villages
+const VILLAGE_PAGE_SIZE = 5000;
+const JOB_POLL_INTERVAL_MS = 1000;
+
+// Must match the server's API_KEY when one is set; adding, activating and removing
+// servers are rejected with 401 otherwise
+const API_KEY = import.meta.env.VITE_API_KEY;
+const apiKeyHeaders = (): Record<string, string> => (API_KEY ? { 'X-API-Key': API_KEY } : {});
+
 interface AfkSearchParams {
   quadrant: string;
   days: number;
@@ -104,6 +113,7 @@ function App() {
   const [allianceInfo, setAllianceInfo] = useState<AllianceInfo | null>(null);
   const [loadingAllianceInfo, setLoadingAllianceInfo] = useState(false);
   const [allianceInfoError, setAllianceInfoError] = useState<string | null>(null);
+  const [showNature, setShowNature] = useState(false);
   
   const serverUrl = 'http://127.0.0.1:3001'; // Fixed server URL
 
@@ -140,20 +150,31 @@ function App() {
     }
   };
 
-  const fetchVillages = async () => {
+  // The toggle passes its new value, since state updates only land on the next render
+  const fetchVillages = async (includeNature: boolean = showNature) => {
     try {
       // Only show loading on initial load
       if (villages.length === 0) {
         setLoading(true);
       }
-      const response = await fetch(`${serverUrl}/api/villages`);
-      if (response.ok) {
-        const data = await response.json();
-        setVillages(data);
-        setError(''); // Clear error on success
-      } else {
-        setError('Failed to fetch villages');
+      // /api/villages is paginated; keep requesting pages until X-Total-Count is reached
+      const allVillages: Village[] = [];
+      let total = Infinity;
+      while (allVillages.length < total) {
+        const response = await fetch(
+          `${serverUrl}/api/villages?limit=${VILLAGE_PAGE_SIZE}&offset=${allVillages.length}&include_nature=${includeNature}`
+        );
+        if (!response.ok) {
+          setError('Failed to fetch villages');
+          return;
+        }
+        const page: Village[] = await response.json();
+        total = Number(response.headers.get('X-Total-Count') ?? allVillages.length + page.length);
+        allVillages.push(...page);
+        if (page.length < VILLAGE_PAGE_SIZE) break;
       }
+      setVillages(allVillages);
+      setError(''); // Clear error on success
     } catch (err) {
       setError('Failed to fetch data');
       console.error('Fetch error:', err);
@@ -162,6 +183,17 @@ function App() {
     }
   };
 
+  // Loads run as background jobs; poll until this one has finished either way
+  const waitForJob = async (jobId: number) => {
+    for (;;) {
+      const response = await fetch(`${serverUrl}/api/jobs/${jobId}`);
+      if (!response.ok) return null;
+      const job = (await response.json()).data;
+      if (job.state === 'done' || job.state === 'failed') return job;
+      await new Promise((resolve) => setTimeout(resolve, JOB_POLL_INTERVAL_MS));
+    }
+  };
+
   const fetchServers = async () => {
     try {
       const response = await fetch(`${serverUrl}/api/servers`);
@@ -187,6 +219,7 @@ function App() {
         method: 'POST',
         headers: {
           'Content-Type': 'application/json',
+          ...apiKeyHeaders(),
         },
         body: JSON.stringify({
           name: newServerName.trim(),
@@ -195,10 +228,20 @@ function App() {
       });
 
       if (response.ok) {
+        const result = await response.json();
         setNewServerName('');
         setNewServerUrl('');
         setShowAddServer(false);
         await fetchServers(); // Refresh server list
+
+        // The first server is activated and loaded in the background
+        if (result.job_id != null) {
+          const job = await waitForJob(result.job_id);
+          if (job?.state === 'failed') {
+            setError(`Failed to load data: ${job.message}`);
+          }
+          await fetchVillages();
+        }
       } else {
         setError('Failed to add server');
       }
@@ -212,6 +255,7 @@ function App() {
     try {
       const response = await fetch(`${serverUrl}/api/servers/${serverId}/activate`, {
         method: 'PUT',
+        headers: apiKeyHeaders(),
       });
 
       if (response.ok) {
@@ -226,6 +270,14 @@ function App() {
         }
         
         await fetchServers(); // Refresh server list
+
+        // The data loads in the background; refetch once the job is through
+        if (result.job_id != null) {
+          const job = await waitForJob(result.job_id);
+          if (job?.state === 'failed') {
+            setError(`Failed to load data: ${job.message}`);
+          }
+        }
         await fetchVillages(); // Refresh villages for new server
       } else {
         setError('Failed to set active server');
@@ -240,6 +292,7 @@ function App() {
     try {
       const response = await fetch(`${serverUrl}/api/servers/${serverId}`, {
         method: 'DELETE',
+        headers: apiKeyHeaders(),
       });
 
       if (response.ok) {
@@ -389,6 +442,17 @@ function App() {
                 </option>
               ))}
             </select>
+            <label className="nature-toggle">
+              <input
+                type="checkbox"
+                checked={showNature}
+                onChange={(e) => {
+                  setShowNature(e.target.checked);
+                  fetchVillages(e.target.checked);
+                }}
+              />
+              Show nature villages
+            </label>
           </div>
 
           {currentServer && (
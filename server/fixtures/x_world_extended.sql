@@ -0,0 +1,4 @@
+-- Classic Travian export with capital, isWW and wwname after population
+INSERT INTO `x_world` VALUES (22028,173,146,5,31912,'Natars 173|146',1,'Natars',0,'',498,NULL,FALSE,NULL);
+INSERT INTO `x_world` VALUES (40118,-5,101,1,18012,'Aachen',4021,'Kuno',112,'Bären',812,TRUE,FALSE,NULL);
+INSERT INTO `x_world` VALUES (80523,-12,1,2,19044,'WW Village',377,'Edda',112,'Bären',1045,FALSE,TRUE,'Wonder of Bären');
@@ -0,0 +1,4 @@
+-- Travian Legends export: population, region, isCapital, isCity, hasHarbor, victoryPoints
+INSERT INTO `x_world` VALUES (40118,-5,101,1,18012,'Aachen',4021,'Kuno',112,'Bären',812,'Rhenus',TRUE,TRUE,FALSE,37);
+INSERT INTO `x_world` VALUES (80523,-12,1,2,19044,'Harbour',377,'Edda',112,'Bären',1045,'Rhenus',FALSE,FALSE,TRUE,12);
+INSERT INTO `x_world` VALUES (120001,88,-99,3,20100,'Lone',990,'Solo',0,'',156,'Gallia',TRUE,FALSE,FALSE,0);
@@ -0,0 +1,36 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use crate::error::ApiError;
+use std::sync::Arc;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+pub async fn require_api_key(
+    State(expected): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let provided = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), expected.as_bytes()) => Ok(next.run(request).await),
+        Some(_) => Err(ApiError::Unauthorized("Invalid API key".to_string())),
+        None => Err(ApiError::Unauthorized(format!("Missing {} header", API_KEY_HEADER))),
+    }
+}
+
+// Compares every byte regardless of where the first mismatch is, so response timing
+// does not reveal how much of a guessed key was correct. Only the length can leak.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
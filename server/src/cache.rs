@@ -0,0 +1,121 @@
+use crate::database::{SnapshotVersion, WorldInfo};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+struct CachedWorldInfo {
+    version: SnapshotVersion,
+    info: WorldInfo,
+    cached_at: Instant,
+}
+
+// World info only changes along with the snapshot, so results are cached per server
+// and keyed by the SnapshotVersion they were computed from. A newer table, a same-day
+// reload or an edit changes the version and misses the cache on its own; invalidate()
+// just frees the entry early.
+pub struct WorldInfoCache {
+    entries: Mutex<HashMap<i32, CachedWorldInfo>>,
+    capacity: usize,
+}
+
+impl WorldInfoCache {
+    pub fn new(capacity: usize) -> Self {
+        WorldInfoCache {
+            entries: Mutex::new(HashMap::new()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn get(&self, server_id: i32, version: &SnapshotVersion) -> Option<WorldInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&server_id)
+            .filter(|cached| cached.version == *version)
+            .map(|cached| cached.info.clone())
+    }
+
+    pub fn insert(&self, server_id: i32, version: SnapshotVersion, info: WorldInfo) {
+        let mut entries = self.entries.lock().unwrap();
+
+        // Evict the oldest entry when a new server would exceed the bound
+        if !entries.contains_key(&server_id) && entries.len() >= self.capacity {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.cached_at)
+                .map(|(id, _)| *id)
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(server_id, CachedWorldInfo {
+            version,
+            info,
+            cached_at: Instant::now(),
+        });
+    }
+
+    pub fn invalidate(&self, server_id: i32) {
+        self.entries.lock().unwrap().remove(&server_id);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world_info(total_villages: i32) -> WorldInfo {
+        WorldInfo { tribe_stats: Vec::new(), top_players: Vec::new(), total_villages, total_population: 0 }
+    }
+
+    fn version(day: u32, village_count: i64) -> SnapshotVersion {
+        SnapshotVersion {
+            server_id: 1,
+            date: chrono::NaiveDate::from_ymd_opt(2024, 5, day).unwrap(),
+            map_size: 401,
+            village_count,
+            last_modified: None,
+        }
+    }
+
+    fn snapshot(day: u32) -> SnapshotVersion {
+        version(day, 10)
+    }
+
+    #[test]
+    fn changed_snapshot_version_misses() {
+        let cache = WorldInfoCache::new(4);
+        cache.insert(1, snapshot(1), world_info(10));
+
+        assert_eq!(cache.get(1, &snapshot(1)).map(|info| info.total_villages), Some(10));
+        assert!(cache.get(1, &snapshot(2)).is_none());
+        assert!(cache.get(1, &version(1, 11)).is_none());
+        assert!(cache.get(2, &snapshot(1)).is_none());
+    }
+
+    #[test]
+    fn invalidate_drops_only_that_server() {
+        let cache = WorldInfoCache::new(4);
+        cache.insert(1, snapshot(1), world_info(10));
+        cache.insert(2, snapshot(1), world_info(20));
+        cache.invalidate(1);
+
+        assert!(cache.get(1, &snapshot(1)).is_none());
+        assert!(cache.get(2, &snapshot(1)).is_some());
+    }
+
+    #[test]
+    fn full_cache_evicts_the_oldest_server() {
+        let cache = WorldInfoCache::new(2);
+        cache.insert(1, snapshot(1), world_info(10));
+        cache.insert(2, snapshot(1), world_info(20));
+        cache.insert(3, snapshot(1), world_info(30));
+
+        assert!(cache.get(1, &snapshot(1)).is_none());
+        assert!(cache.get(2, &snapshot(1)).is_some() && cache.get(3, &snapshot(1)).is_some());
+    }
+}
@@ -0,0 +1,68 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use crate::database::SnapshotVersion;
+
+// Validators for responses that only change along with the underlying snapshot, so
+// clients can revalidate with If-None-Match / If-Modified-Since and get a 304 back
+// instead of the full body
+pub struct Validators {
+    etag: String,
+    last_modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Validators {
+    pub fn for_snapshot(version: &SnapshotVersion) -> Self {
+        let modified_micros = version.last_modified.map_or(0, |modified| modified.timestamp_micros());
+        Validators {
+            etag: format!(
+                "\"{}-{}-{}-{}-{}\"",
+                version.server_id,
+                version.date.format("%Y%m%d"),
+                version.map_size,
+                version.village_count,
+                modified_micros
+            ),
+            last_modified: version.last_modified,
+        }
+    }
+
+    // If-None-Match wins when present; If-Modified-Since is only compared to the second,
+    // which is all an HTTP date can express
+    pub fn not_modified(&self, request_headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+            let Ok(if_none_match) = if_none_match.to_str() else {
+                return false;
+            };
+            return if_none_match
+                .split(',')
+                .map(|tag| tag.trim())
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == self.etag);
+        }
+
+        let (Some(last_modified), Some(if_modified_since)) = (self.last_modified, request_headers.get(header::IF_MODIFIED_SINCE)) else {
+            return false;
+        };
+        if_modified_since
+            .to_str()
+            .ok()
+            .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok())
+            .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+    }
+
+    // Sent on both the 200 and the 304. no-cache makes browsers revalidate every time
+    // rather than guess a freshness lifetime from Last-Modified.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        // Weak, since the gzip and identity encodings of a response share one tag
+        if let Ok(etag) = HeaderValue::from_str(&format!("W/{}", self.etag)) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            let http_date = last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            if let Ok(last_modified) = HeaderValue::from_str(&http_date) {
+                headers.insert(header::LAST_MODIFIED, last_modified);
+            }
+        }
+        headers
+    }
+}
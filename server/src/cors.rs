@@ -0,0 +1,31 @@
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use crate::auth::API_KEY_HEADER;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+// The Vite dev server, which is where the client runs out of the box
+pub const DEFAULT_ALLOWED_ORIGINS: &str = "http://localhost:5173,http://127.0.0.1:5173";
+
+// DEV_MODE=true relaxes CORS to allow any origin
+pub fn cors_layer(dev_mode: bool, origins: Vec<HeaderValue>) -> CorsLayer {
+    if dev_mode {
+        return CorsLayer::permissive();
+    }
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE, Method::OPTIONS])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            HeaderName::from_static(API_KEY_HEADER),
+            header::IF_NONE_MATCH,
+            header::IF_MODIFIED_SINCE,
+        ])
+        // Pagination totals, export filenames, rate limit hints and cache validators have to be readable from the client
+        .expose_headers([
+            HeaderName::from_static("x-total-count"),
+            header::CONTENT_DISPOSITION,
+            header::RETRY_AFTER,
+            header::ETAG,
+            header::LAST_MODIFIED,
+        ])
+}
@@ -1,7 +1,38 @@
-use sqlx::{PgPool, Row};
+use sqlx::{postgres::{PgPoolOptions, PgRow}, Acquire, FromRow, PgConnection, PgPool, Postgres, QueryBuilder, Row};
 use anyhow::Result;
+use crate::cache::WorldInfoCache;
+use crate::fetch::MapFetcher;
+use crate::geo;
+use crate::load_lock::ServerLoadLocks;
+use crate::sample;
+use crate::telemetry::{self, QueryTimer};
+use crate::webhook::{LoadSummary, Webhook};
 use crate::MapData;
+use futures_util::TryStreamExt;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Every village query selects these columns under their table names; the unsigned
+// fields in MapData are stored as INTEGER
+impl<'r> FromRow<'r, PgRow> for MapData {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(MapData {
+            id: row.try_get::<i32, _>("id")? as u32,
+            name: row.try_get("village")?,
+            x: row.try_get("x")?,
+            y: row.try_get("y")?,
+            population: row.try_get::<i32, _>("population")? as u32,
+            player: row.try_get("player")?,
+            alliance: row.try_get("alliance")?,
+            worldid: row.try_get::<Option<i32>, _>("worldid")?.map(|v| v as u32),
+            tribe: row.try_get::<Option<i32>, _>("tid")?.map(|v| v as u32),
+        })
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Server {
@@ -9,25 +40,139 @@ pub struct Server {
     pub name: String,
     pub url: String,
     pub is_active: bool,
+    // The override, else the detected size; None until either exists, in which case
+    // distance and region queries fall back to MAP_SIZE
+    pub map_size: Option<i32>,
+}
+
+impl Server {
+    pub fn map_size_or(&self, default_map_size: i32) -> i32 {
+        self.map_size.unwrap_or(default_map_size)
+    }
+
+    // Lists the size queries actually use, fallback included, as /api/servers documents
+    pub fn with_map_size_or(mut self, default_map_size: i32) -> Self {
+        self.map_size = Some(self.map_size_or(default_map_size));
+        self
+    }
+}
+
+// Shared by every query that builds a Server; the override wins over the detected size
+const SERVER_COLUMNS: &str = "id, name, url, is_active, COALESCE(map_size, detected_map_size) AS map_size";
+
+fn server_from_row(row: &PgRow) -> Server {
+    Server {
+        id: row.get("id"),
+        name: row.get("name"),
+        url: row.get("url"),
+        is_active: row.get("is_active"),
+        map_size: row.get::<Option<i32>, _>("map_size").filter(|value| *value > 0),
+    }
+}
+
+// Classic Travian maps span -200..=200 on both axes
+pub const DEFAULT_MAP_SIZE: i32 = 401;
+
+pub const DEFAULT_TABLE_RETENTION_DAYS: usize = 10;
+
+// `default_map_size` is the configured MAP_SIZE, used when the server has neither an
+// override nor a detected size
+pub async fn get_map_size_for_server(pool: &PgPool, server_id: i32, default_map_size: i32) -> Result<i32> {
+    let map_size: Option<i32> = sqlx::query_scalar("SELECT COALESCE(map_size, detected_map_size) FROM servers WHERE id = $1")
+        .bind(server_id)
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+
+    Ok(map_size.filter(|value| *value > 0).unwrap_or(default_map_size))
+}
+
+// Maps are square and centred on (0|0), so the outermost village on either axis gives
+// the size. A young server with no villages on the edge yet reads smaller than it is;
+// set the servers.map_size override in that case. Returns None without any villages.
+pub async fn detect_map_size(pool: &PgPool, server_id: i32) -> Result<Option<i32>> {
+    let Some(table_name) = get_latest_table_for_server(pool, server_id).await? else {
+        return Ok(None);
+    };
+
+    let query = format!(
+        "SELECT GREATEST(MAX(ABS(x)), MAX(ABS(y))) FROM {} WHERE server_id = $1",
+        table_name
+    );
+    let extent: Option<i32> = sqlx::query_scalar(&query).bind(server_id).fetch_one(pool).await?;
+    let Some(extent) = extent else {
+        return Ok(None);
+    };
+
+    let map_size = extent * 2 + 1;
+    sqlx::query("UPDATE servers SET detected_map_size = $2 WHERE id = $1")
+        .bind(server_id)
+        .bind(map_size)
+        .execute(pool)
+        .await?;
+
+    tracing::info!(server_id, map_size, "Detected map size");
+    Ok(Some(map_size))
+}
+
+// `default_retention` is the configured TABLE_RETENTION_DAYS, used when the server has
+// no retention_days of its own
+pub async fn get_table_retention_for_server(pool: &PgPool, server_id: i32, default_retention: usize) -> Result<usize> {
+    let retention: Option<i32> = sqlx::query_scalar("SELECT retention_days FROM servers WHERE id = $1")
+        .bind(server_id)
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+
+    Ok(retention
+        .filter(|value| *value > 0)
+        .map(|value| value as usize)
+        .unwrap_or(default_retention))
+}
+
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+pub const DEFAULT_ACQUIRE_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 600;
+
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Duration,
 }
 
-pub async fn create_pool(database_url: &str) -> Result<PgPool> {
-    let pool = PgPool::connect(database_url).await?;
+pub async fn create_pool(database_url: &str, config: &PoolConfig) -> Result<PgPool> {
+    let pool = pool_options(config)
+        .connect(database_url)
+        .await?;
     Ok(pool)
 }
 
-fn get_table_name_for_server_and_date(server_id: i32, date: chrono::NaiveDate) -> String {
-    format!("villages_server_{}_{}", server_id, date.format("%Y_%m_%d"))
+// For a read replica. Sessions are made read-only so a write routed here by mistake
+// fails instead of trying its luck against a standby.
+pub async fn create_read_pool(database_url: &str, config: &PoolConfig) -> Result<PgPool> {
+    let pool = pool_options(config)
+        .after_connect(|conn, _| {
+            Box::pin(async move {
+                sqlx::query("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY")
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            })
+        })
+        .connect(database_url)
+        .await?;
+    Ok(pool)
 }
 
-fn get_table_name_for_date(date: chrono::NaiveDate) -> String {
-    // Default server (id = 1) for backward compatibility
-    get_table_name_for_server_and_date(1, date)
+fn pool_options(config: &PoolConfig) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .acquire_timeout(config.acquire_timeout)
+        .idle_timeout(config.idle_timeout)
 }
 
-fn get_today_table_name() -> String {
-    let today = chrono::Utc::now().date_naive();
-    get_table_name_for_date(today)
+fn get_table_name_for_server_and_date(server_id: i32, date: chrono::NaiveDate) -> String {
+    format!("villages_server_{}_{}", server_id, date.format("%Y_%m_%d"))
 }
 
 pub async fn create_table_for_server_and_date(pool: &PgPool, server_id: i32, date: chrono::NaiveDate) -> Result<String> {
@@ -53,20 +198,57 @@ pub async fn create_table_for_server_and_date(pool: &PgPool, server_id: i32, dat
             capital VARCHAR(10),
             isWW BOOLEAN DEFAULT FALSE,
             wwname VARCHAR(255),
+            first_seen DATE NOT NULL DEFAULT '{}',
             created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
             updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
         )
         "#,
-        table_name
+        table_name,
+        date
     );
     
     sqlx::query(&create_query)
         .execute(pool)
         .await?;
 
-    // Create indexes for the new table
-    let coord_index = format!("CREATE INDEX IF NOT EXISTS idx_{}_coordinates ON {} (server_id, x, y)", table_name, table_name);
-    sqlx::query(&coord_index).execute(pool).await?;
+    // Tables created before first_seen existed get the column with the table's own date
+    let first_seen_column = format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS first_seen DATE DEFAULT '{}'", table_name, date);
+    sqlx::query(&first_seen_column).execute(pool).await?;
+
+    // Create indexes for the new table. One village per tile, which reloads upsert on
+    let position_index_name = format!("idx_{}_position", table_name);
+    let has_position_index: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM pg_indexes WHERE schemaname = 'public' AND tablename = $1 AND indexname = $2)"
+    )
+    .bind(&table_name)
+    .bind(&position_index_name)
+    .fetch_one(pool)
+    .await?;
+
+    if !has_position_index {
+        // Tables from before the upsert can hold several rows per tile, which would fail the
+        // unique index; the most recently updated row of each tile is kept
+        let dedup_query = format!(
+            r#"
+            DELETE FROM {table} a
+            USING {table} b
+            WHERE a.server_id = b.server_id AND a.x = b.x AND a.y = b.y
+              AND (COALESCE(a.updated_at, '-infinity'), a.id) < (COALESCE(b.updated_at, '-infinity'), b.id)
+            "#,
+            table = table_name
+        );
+        let removed = sqlx::query(&dedup_query).execute(pool).await?.rows_affected();
+        if removed > 0 {
+            tracing::warn!(table = %table_name, removed, "Removed duplicate villages before adding the position index");
+        }
+
+        let position_index = format!("CREATE UNIQUE INDEX IF NOT EXISTS {} ON {} (server_id, x, y)", position_index_name, table_name);
+        sqlx::query(&position_index).execute(pool).await?;
+
+        // The unique index covers the same columns as the old non-unique one
+        let old_index = format!("DROP INDEX IF EXISTS idx_{}_coordinates", table_name);
+        sqlx::query(&old_index).execute(pool).await?;
+    }
 
     let pop_index = format!("CREATE INDEX IF NOT EXISTS idx_{}_population ON {} (server_id, population)", table_name, table_name);
     sqlx::query(&pop_index).execute(pool).await?;
@@ -74,6 +256,21 @@ pub async fn create_table_for_server_and_date(pool: &PgPool, server_id: i32, dat
     let world_index = format!("CREATE INDEX IF NOT EXISTS idx_{}_worldid ON {} (server_id, worldid)", table_name, table_name);
     sqlx::query(&world_index).execute(pool).await?;
 
+    // The position index covers x ranges; this one lets bounding boxes narrow on y too
+    let y_index = format!("CREATE INDEX IF NOT EXISTS idx_{}_y ON {} (server_id, y)", table_name, table_name);
+    sqlx::query(&y_index).execute(pool).await?;
+
+    // For single-player, single-alliance and tribe filters. Map-wide aggregations read most
+    // rows and still scan, which is the faster plan for them.
+    let player_index = format!("CREATE INDEX IF NOT EXISTS idx_{}_player ON {} (server_id, player)", table_name, table_name);
+    sqlx::query(&player_index).execute(pool).await?;
+
+    let alliance_index = format!("CREATE INDEX IF NOT EXISTS idx_{}_alliance ON {} (server_id, alliance)", table_name, table_name);
+    sqlx::query(&alliance_index).execute(pool).await?;
+
+    let tribe_index = format!("CREATE INDEX IF NOT EXISTS idx_{}_tid ON {} (server_id, tid)", table_name, table_name);
+    sqlx::query(&tribe_index).execute(pool).await?;
+
     Ok(table_name)
 }
 
@@ -99,86 +296,236 @@ pub async fn create_tables(pool: &PgPool) -> Result<()> {
     .execute(pool)
     .await?;
 
+    // Per-server map size override; NULL falls back to MAP_SIZE / DEFAULT_MAP_SIZE
+    sqlx::query("ALTER TABLE servers ADD COLUMN IF NOT EXISTS map_size INTEGER")
+        .execute(pool)
+        .await?;
+
+    // Map size derived from the latest snapshot, recomputed on every load; the override wins
+    sqlx::query("ALTER TABLE servers ADD COLUMN IF NOT EXISTS detected_map_size INTEGER")
+        .execute(pool)
+        .await?;
+
+    // Per-server snapshot retention override; NULL falls back to TABLE_RETENTION_DAYS
+    sqlx::query("ALTER TABLE servers ADD COLUMN IF NOT EXISTS retention_days INTEGER")
+        .execute(pool)
+        .await?;
+
+    // One row per load attempt, so the last successful load and any failures after it
+    // can be reported without inspecting the snapshot tables
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS load_history (
+            id SERIAL PRIMARY KEY,
+            server_id INTEGER NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+            date DATE NOT NULL,
+            loaded_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            village_count INTEGER NOT NULL DEFAULT 0,
+            source_url VARCHAR(512) NOT NULL,
+            status VARCHAR(16) NOT NULL,
+            error TEXT
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Which x_world layout (see X_WORLD_LAYOUTS) a successful load was parsed with
+    sqlx::query("ALTER TABLE load_history ADD COLUMN IF NOT EXISTS layout VARCHAR(32)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_load_history_server ON load_history(server_id, loaded_at DESC)")
+        .execute(pool)
+        .await?;
+
+    // Coordinates to keep an eye on. Each load compares them with the previous snapshot
+    // and records what changed in watchlist_changes.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS watchlist (
+            id SERIAL PRIMARY KEY,
+            server_id INTEGER NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+            x INTEGER NOT NULL,
+            y INTEGER NOT NULL,
+            label VARCHAR(255),
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            UNIQUE (server_id, x, y)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS watchlist_changes (
+            id SERIAL PRIMARY KEY,
+            watch_id INTEGER NOT NULL REFERENCES watchlist(id) ON DELETE CASCADE,
+            kind VARCHAR(16) NOT NULL,
+            from_date DATE NOT NULL,
+            to_date DATE NOT NULL,
+            village VARCHAR(255) NOT NULL,
+            old_player VARCHAR(255),
+            new_player VARCHAR(255),
+            old_alliance VARCHAR(255),
+            new_alliance VARCHAR(255),
+            old_population INTEGER,
+            new_population INTEGER,
+            detected_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_watchlist_changes_watch ON watchlist_changes(watch_id, to_date DESC)")
+        .execute(pool)
+        .await?;
+
     // Create the default villages table (for backward compatibility)
     let today = chrono::Utc::now().date_naive();
     create_table_for_date(pool, today).await?;
     Ok(())
 }
 
-pub async fn get_available_dates(pool: &PgPool) -> Result<Vec<(chrono::NaiveDate, i32)>> {
-    // Query for all tables that match the villages_YYYY_MM_DD pattern
-    let rows = sqlx::query(
-        r#"
-        SELECT table_name 
-        FROM information_schema.tables 
-        WHERE table_schema = 'public' 
-        AND table_name LIKE 'villages_%' 
-        AND table_name ~ '^villages_[0-9]{4}_[0-9]{2}_[0-9]{2}$'
-        ORDER BY table_name DESC
-        "#
-    )
-    .fetch_all(pool)
-    .await?;
-
-    let mut result = Vec::new();
+pub async fn cleanup_old_tables(pool: &PgPool, default_retention: usize) -> Result<()> {
+    // Each server keeps its own dated tables, so retention is applied per server
+    let servers = get_all_servers(pool).await?;
     
-    for row in rows {
-        let table_name: String = row.get("table_name");
-        
-        // Extract date from table name (format: villages_YYYY_MM_DD)
-        if let Some(date_part) = table_name.strip_prefix("villages_") {
-            if let Ok(date) = chrono::NaiveDate::parse_from_str(date_part, "%Y_%m_%d") {
-                // Get village count for this table
-                let count_query = format!("SELECT COUNT(*) FROM {}", table_name);
-                let count: i64 = sqlx::query_scalar(&count_query)
-                    .fetch_one(pool)
-                    .await?;
-                
-                result.push((date, count as i32));
-            }
-        }
+    for server in servers {
+        cleanup_old_tables_for_server(pool, server.id, default_retention).await?;
     }
     
-    Ok(result)
+    Ok(())
 }
 
-pub async fn cleanup_old_tables(pool: &PgPool) -> Result<()> {
-    let available_dates = get_available_dates(pool).await?;
+pub async fn cleanup_old_tables_for_server(pool: &PgPool, server_id: i32, default_retention: usize) -> Result<()> {
+    let retention = get_table_retention_for_server(pool, server_id, default_retention).await?;
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
     
-    // Keep only the last 10 tables
-    if available_dates.len() > 10 {
-        let tables_to_drop = &available_dates[10..];
+    // Keep only the most recent tables (dates are sorted DESC)
+    if available_dates.len() > retention {
+        let tables_to_drop = &available_dates[retention..];
         
         for (date, _) in tables_to_drop {
-            let table_name = get_table_name_for_date(*date);
+            let table_name = get_table_name_for_server_and_date(server_id, *date);
             let drop_query = format!("DROP TABLE IF EXISTS {}", table_name);
             sqlx::query(&drop_query).execute(pool).await?;
-            println!("Dropped old table: {}", table_name);
+            tracing::info!(server_id, table = %table_name, "Dropped old snapshot table");
         }
     }
     
     Ok(())
 }
 
-pub async fn insert_sample_data(pool: &PgPool) -> Result<()> {
-    // Sample data insertion is now optional and disabled by default
-    // The database starts empty and ready for real Travian server data
-    println!("Sample data insertion skipped - database ready for real data");
-    Ok(())
+// Dev-mode sample data: made-up villages in the active server's snapshots for today and
+// the `days - 1` days before it, so the history, AFK and growth views have something to
+// compare. Tiles that hold a village in any of those snapshots are left alone, and
+// generated ids start above the ones already there. Returns how many villages today's
+// snapshot gained.
+pub async fn insert_sample_data(pool: &PgPool, count: usize, days: usize, seed: Option<u64>, default_map_size: i32) -> Result<usize> {
+    let _timer = QueryTimer::start("insert_sample_data");
+    let Some(server) = get_active_server(pool).await? else {
+        return Err(NoActiveServer.into());
+    };
+
+    let today = chrono::Utc::now().date_naive();
+    let mut tables = Vec::with_capacity(days);
+    for days_ago in 0..days {
+        let date = today - chrono::Duration::days(days_ago as i64);
+        tables.push(create_table_for_server_and_date(pool, server.id, date).await?);
+    }
+
+    let mut occupied = HashSet::new();
+    let mut offsets = sample::IdOffsets::default();
+    for table_name in &tables {
+        let rows = sqlx::query(&format!("SELECT x, y FROM {} WHERE server_id = $1", table_name))
+            .bind(server.id)
+            .fetch_all(pool)
+            .await?;
+        occupied.extend(rows.iter().map(|row| (row.get::<i32, _>("x"), row.get::<i32, _>("y"))));
+
+        let row = sqlx::query(&format!(
+            "SELECT COALESCE(MAX(uid), 0) as uid, COALESCE(MAX(aid), 0) as aid, COALESCE(MAX(vid), 0) as vid
+             FROM {} WHERE server_id = $1",
+            table_name
+        ))
+        .bind(server.id)
+        .fetch_one(pool)
+        .await?;
+        offsets.uid = offsets.uid.max(row.get("uid"));
+        offsets.aid = offsets.aid.max(row.get("aid"));
+        offsets.vid = offsets.vid.max(row.get("vid"));
+    }
+
+    let villages = sample::generate_villages(count, days, seed, server.map_size_or(default_map_size), &mut occupied, &offsets);
+
+    let mut tx = pool.begin().await?;
+    for (days_ago, table_name) in tables.iter().enumerate() {
+        let snapshot: Vec<ParsedVillage> = villages
+            .iter()
+            .filter_map(|village| {
+                Some(ParsedVillage {
+                    worldid: village.worldid,
+                    x: village.x,
+                    y: village.y,
+                    tid: Some(village.tid),
+                    vid: Some(village.vid),
+                    village: village.name.clone(),
+                    uid: Some(village.uid),
+                    player: Some(village.player.clone()),
+                    aid: village.aid,
+                    alliance: village.alliance.clone(),
+                    population: village.population_days_ago(days_ago)?,
+                    capital: Some(if village.capital { "TRUE" } else { "FALSE" }.to_string()),
+                    is_ww: village.ww_name.is_some(),
+                    ww_name: village.ww_name.clone(),
+                })
+            })
+            .collect();
+
+        for batch in snapshot.chunks(INSERT_BATCH_SIZE) {
+            insert_parsed_villages_to_table_with_server(&mut tx, batch, table_name, server.id).await?;
+        }
+    }
+    tx.commit().await?;
+
+    tracing::info!(server_id = server.id, villages = villages.len(), days, "Inserted sample data");
+    Ok(villages.len())
+}
+
+// Nature villages are oases and animal camps rather than player villages
+pub const NATURE_TRIBE_ID: i32 = 4;
+
+pub const NATARS_TRIBE_ID: i32 = 5;
+
+// SQL condition excluding nature villages; NULL tids are player villages from old dumps
+fn not_nature(tid_column: &str) -> String {
+    format!("{} IS DISTINCT FROM {}", tid_column, NATURE_TRIBE_ID)
+}
+
+// Map queries leave nature out unless asked
+fn nature_condition(include_nature: bool) -> String {
+    if include_nature {
+        String::new()
+    } else {
+        format!(" AND {}", not_nature("tid"))
+    }
 }
 
-pub async fn get_all_villages(pool: &PgPool) -> Result<Vec<MapData>> {
+pub async fn get_all_villages(pool: &PgPool, include_nature: bool) -> Result<Vec<MapData>> {
     // Get the active server
     let active_server = get_active_server(pool).await?;
     
     if let Some(server) = active_server {
-        get_villages_for_server(pool, server.id).await
+        get_villages_for_server(pool, server.id, include_nature).await
     } else {
         Ok(Vec::new()) // No active server
     }
 }
 
-pub async fn get_villages_for_server(pool: &PgPool, server_id: i32) -> Result<Vec<MapData>> {
+pub async fn get_villages_for_server(pool: &PgPool, server_id: i32, include_nature: bool) -> Result<Vec<MapData>> {
     // Get the latest table for this server (most recent date)
     let available_dates = get_available_dates_for_server(pool, server_id).await?;
     
@@ -187,7 +534,7 @@ pub async fn get_villages_for_server(pool: &PgPool, server_id: i32) -> Result<Ve
     }
     
     let latest_date = available_dates[0].0;
-    get_villages_by_server_and_date(pool, server_id, latest_date).await
+    get_villages_by_server_and_date(pool, server_id, latest_date, include_nature).await
 }
 
 pub async fn get_available_dates_for_server(pool: &PgPool, server_id: i32) -> Result<Vec<(chrono::NaiveDate, i32)>> {
@@ -231,7 +578,12 @@ pub async fn get_available_dates_for_server(pool: &PgPool, server_id: i32) -> Re
     Ok(result)
 }
 
-pub async fn get_villages_by_server_and_date(pool: &PgPool, server_id: i32, date: chrono::NaiveDate) -> Result<Vec<MapData>> {
+pub async fn get_villages_by_server_and_date(
+    pool: &PgPool,
+    server_id: i32,
+    date: chrono::NaiveDate,
+    include_nature: bool,
+) -> Result<Vec<MapData>> {
     let table_name = get_table_name_for_server_and_date(server_id, date);
     
     // Check if table exists
@@ -247,8 +599,9 @@ pub async fn get_villages_by_server_and_date(pool: &PgPool, server_id: i32, date
     }
     
     let query = format!(
-        "SELECT id, village, x, y, population, player, alliance, worldid FROM {} WHERE server_id = $1 ORDER BY population DESC",
-        table_name
+        "SELECT id, village, x, y, population, player, alliance, worldid, tid FROM {} WHERE server_id = $1{} ORDER BY population DESC",
+        table_name,
+        nature_condition(include_nature)
     );
     
     let rows = sqlx::query(&query)
@@ -257,732 +610,4073 @@ pub async fn get_villages_by_server_and_date(pool: &PgPool, server_id: i32, date
         .await?;
 
     let villages: Vec<MapData> = rows
-        .into_iter()
-        .map(|row| MapData {
-            id: row.get::<i32, _>("id") as u32,
-            name: row.get("village"),
-            x: row.get("x"),
-            y: row.get("y"),
-            population: row.get::<i32, _>("population") as u32,
-            player: row.get("player"),
-            alliance: row.get("alliance"),
-            worldid: row.get::<Option<i32>, _>("worldid").map(|v| v as u32),
-        })
-        .collect();
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
 
     Ok(villages)
 }
 
-pub async fn add_village(pool: &PgPool, name: &str, x: i32, y: i32, population: u32) -> Result<MapData> {
-    let row = sqlx::query(
-        "INSERT INTO villages (village, x, y, population, player, alliance) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, village, x, y, population, player, alliance, worldid"
-    )
-    .bind(name)
-    .bind(x)
-    .bind(y)
-    .bind(population as i32)
-    .bind("Unknown Player")
-    .bind("No Alliance")
-    .fetch_one(pool)
-    .await?;
-
-    Ok(MapData {
-        id: row.get::<i32, _>("id") as u32,
-        name: row.get("village"),
-        x: row.get("x"),
-        y: row.get("y"),
-        population: row.get::<i32, _>("population") as u32,
-        player: row.get("player"),
-        alliance: row.get("alliance"),
-        worldid: row.get::<Option<i32>, _>("worldid").map(|v| v as u32),
-    })
+// Builds the squared distance between a village row and the point bound at
+// `cx`/`cy`, honoring Travian's wrap-around: on a map `size` tiles wide x=200
+// and x=-200 are neighbours, so each axis uses min(|d|, size - |d|).
+// The arithmetic is done in BIGINT so large coordinates cannot overflow.
+fn toroidal_distance_squared_sql(cx: &str, cy: &str, size: &str) -> String {
+    toroidal_distance_squared_between_sql(("x", "y"), (cx, cy), size)
 }
 
-pub async fn update_village_population(pool: &PgPool, id: u32, population: u32) -> Result<Option<MapData>> {
-    let result = sqlx::query(
-        r#"
-        UPDATE villages 
-        SET population = $2, updated_at = NOW() 
-        WHERE id = $1 
-        RETURNING id, village, x, y, population, player, alliance, worldid
-        "#
+// Same as toroidal_distance_squared_sql for two arbitrary coordinate expressions,
+// e.g. the columns of two aliases in a self-join
+fn toroidal_distance_squared_between_sql((ax, ay): (&str, &str), (bx, by): (&str, &str), size: &str) -> String {
+    let dx = format!("MOD(ABS({ax}::BIGINT - {bx}::BIGINT), {size}::BIGINT)");
+    let dy = format!("MOD(ABS({ay}::BIGINT - {by}::BIGINT), {size}::BIGINT)");
+    format!(
+        "(LEAST({dx}, {size}::BIGINT - {dx}) * LEAST({dx}, {size}::BIGINT - {dx}) + LEAST({dy}, {size}::BIGINT - {dy}) * LEAST({dy}, {size}::BIGINT - {dy}))"
     )
-    .bind(id as i32)
-    .bind(population as i32)
-    .fetch_optional(pool)
-    .await?;
-
-    if let Some(row) = result {
-        Ok(Some(MapData {
-            id: row.get::<i32, _>("id") as u32,
-            name: row.get("village"),
-            x: row.get("x"),
-            y: row.get("y"),
-            population: row.get::<i32, _>("population") as u32,
-            player: row.get("player"),
-            alliance: row.get("alliance"),
-            worldid: row.get::<Option<i32>, _>("worldid").map(|v| v as u32),
-        }))
-    } else {
-        Ok(None)
-    }
 }
 
-pub async fn delete_village(pool: &PgPool, id: u32) -> Result<bool> {
-    let result = sqlx::query("DELETE FROM villages WHERE id = $1")
-        .bind(id as i32)
-        .execute(pool)
-        .await?;
+// Index-friendly pre-filter for villages within `radius` of (cx, cy): a box around the
+// center on each axis, split where it wraps past a map edge, so the coordinate indexes
+// can be used before the exact distance is computed. Villages off the map are let
+// through for the distance check, since the distance wraps them back on. Returns an
+// empty string once the box spans the whole map.
+fn bounding_box_sql(cx: i32, cy: i32, radius: i32, map_size: i32) -> String {
+    let (min, max) = geo::coordinate_range(map_size);
+    let axis = |column: &str, center: i32| match geo::wrapped_axis_ranges(center, radius, map_size) {
+        Some(ranges) => {
+            let mut arms: Vec<String> = ranges
+                .iter()
+                .map(|(low, high)| format!("{column} BETWEEN {low} AND {high}"))
+                .collect();
+            arms.push(format!("{column} < {min} OR {column} > {max}"));
+            format!(" AND ({})", arms.join(" OR "))
+        },
+        None => String::new(),
+    };
 
-    Ok(result.rows_affected() > 0)
+    format!("{}{}", axis("x", cx), axis("y", cy))
 }
 
-pub async fn clear_todays_villages(pool: &PgPool) -> Result<()> {
-    let today = chrono::Utc::now().date_naive();
-    let table_name = get_table_name_for_date(today);
-    
-    // Check if table exists
-    let table_exists: bool = sqlx::query_scalar(
-        "SELECT EXISTS (SELECT FROM information_schema.tables WHERE table_schema = 'public' AND table_name = $1)"
-    )
-    .bind(&table_name)
-    .fetch_one(pool)
-    .await?;
-    
-    if table_exists {
-        let delete_query = format!("DELETE FROM {}", table_name);
-        sqlx::query(&delete_query).execute(pool).await?;
-    }
-    
-    Ok(())
+pub async fn get_latest_table_for_server(pool: &PgPool, server_id: i32) -> Result<Option<String>> {
+    Ok(get_latest_data_date_for_server(pool, server_id)
+        .await?
+        .map(|date| get_table_name_for_server_and_date(server_id, date)))
 }
 
-pub async fn execute_sql_with_date_tables(pool: &PgPool, sql_content: &str) -> Result<usize> {
+pub async fn get_villages_near(
+    pool: &PgPool,
+    x: i32,
+    y: i32,
+    radius: i32,
+    map_size: Option<i32>,
+    include_nature: bool,
+    default_map_size: i32,
+) -> Result<Vec<MapData>> {
     // Get the active server
     let active_server = get_active_server(pool).await?;
-    
-    if let Some(server) = active_server {
-        execute_sql_for_server(pool, sql_content, server.id).await
-    } else {
-        Err(anyhow::anyhow!("No active server found"))
-    }
+
+    let server = match active_server {
+        Some(server) => server,
+        None => return Ok(Vec::new()), // No active server
+    };
+
+    let table_name = match get_latest_table_for_server(pool, server.id).await? {
+        Some(table_name) => table_name,
+        None => return Ok(Vec::new()), // No tables available for this server
+    };
+
+    let map_size = match map_size {
+        Some(map_size) => map_size,
+        None => server.map_size_or(default_map_size),
+    };
+
+    // The radius describes a Euclidean circle around (x, y): a village is included
+    // when dx^2 + dy^2 <= radius^2, so a village exactly `radius` tiles away along
+    // an axis is included and one at `radius + 1` is not. dx/dy wrap around the
+    // map edges (see toroidal_distance_squared_sql).
+    let query = format!(
+        r#"
+        SELECT id, village, x, y, population, player, alliance, worldid, tid
+        FROM {}
+        WHERE server_id = $1{}
+        AND {} <= $4{}
+        ORDER BY population DESC
+        "#,
+        table_name,
+        bounding_box_sql(x, y, radius, map_size),
+        toroidal_distance_squared_sql("$2", "$3", "$5"),
+        nature_condition(include_nature)
+    );
+
+    let radius_squared = radius as i64 * radius as i64;
+
+    let rows = sqlx::query(&query)
+        .bind(server.id)
+        .bind(x)
+        .bind(y)
+        .bind(radius_squared)
+        .bind(map_size)
+        .fetch_all(pool)
+        .await?;
+
+    let villages: Vec<MapData> = rows
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(villages)
 }
 
-pub async fn execute_sql_for_server(pool: &PgPool, sql_content: &str, server_id: i32) -> Result<usize> {
-    let today = chrono::Utc::now().date_naive();
-    
-    // Create table for today if it doesn't exist
-    let table_name = create_table_for_server_and_date(pool, server_id, today).await?;
-    
-    // Clear existing data for today for this server
-    let delete_query = format!("DELETE FROM {} WHERE server_id = $1", table_name);
-    sqlx::query(&delete_query).bind(server_id).execute(pool).await?;
-    
-    // Parse the SQL content to extract INSERT statements for x_world table
-    let mut village_count = 0;
-    
-    // Split by lines and process each line
-    for line in sql_content.lines() {
-        let trimmed = line.trim();
-        
-        // Skip empty lines and comments
-        if trimmed.is_empty() || trimmed.starts_with("--") || trimmed.starts_with("/*") {
-            continue;
-        }
-        
-        // Look for INSERT statements for x_world table
-        if trimmed.to_lowercase().contains("insert into") && 
-           (trimmed.to_lowercase().contains("x_world") || trimmed.to_lowercase().contains("`x_world`")) {
-            
-            // Parse Travian x_world format: INSERT INTO `x_world` VALUES (22028,173,146,5,31912,'Natars 173|146′,1,'Natars',0,",498,NULL,FALSE,NULL,NULL,NULL);
-            if let Some(values_start) = trimmed.find("VALUES") {
-                let values_part = &trimmed[values_start + 6..].trim();
-                
-                // Extract the values between parentheses
-                if let Some(start) = values_part.find('(') {
-                    if let Some(end) = values_part.rfind(')') {
-                        let values_str = &values_part[start + 1..end];
-                        
-                        // Parse the comma-separated values
-                        if let Ok(parsed_village) = parse_x_world_values(values_str) {
-                            match insert_parsed_village_to_table_with_server(pool, parsed_village, &table_name, server_id).await {
-                                Ok(_) => village_count += 1,
-                                Err(e) => {
-                                    eprintln!("Failed to insert village: {}", e);
-                                    // Continue with other villages
-                                }
-                            }
-                        } else {
-                            eprintln!("Failed to parse x_world values: {}", values_str);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    
-    // Cleanup old tables (keep only last 10)
-    cleanup_old_tables(pool).await?;
-    
-    Ok(village_count)
-}
+// First search radius for get_nearest_villages; doubled until k villages are found
+const NEAREST_INITIAL_RADIUS: i32 = 16;
 
-struct ParsedVillage {
-    worldid: Option<i32>,
+// The k villages closest to (x, y) on the active server, nearest first, using the same
+// wrap-around distance as get_villages_near. A village standing exactly on (x, y) is
+// skipped unless include_self is set.
+pub async fn get_nearest_villages(
+    pool: &PgPool,
     x: i32,
     y: i32,
-    tid: Option<i32>,
-    vid: Option<i32>,
-    village: String,
-    uid: Option<i32>,
-    player: Option<String>,
-    aid: Option<i32>,
-    alliance: Option<String>,
-    population: i32,
-}
+    k: i64,
+    include_self: bool,
+    default_map_size: i32,
+) -> Result<Vec<MapData>> {
+    let server = match get_active_server(pool).await? {
+        Some(server) => server,
+        None => return Ok(Vec::new()), // No active server
+    };
 
-fn parse_x_world_values(values_str: &str) -> Result<ParsedVillage> {
-    // Split by comma, but be careful with quoted strings
-    let mut parts = Vec::new();
-    let mut current = String::new();
-    let mut in_quotes = false;
-    let mut quote_char = '"';
-    
-    for ch in values_str.chars() {
-        match ch {
-            '"' | '\'' => {
-                if !in_quotes {
-                    in_quotes = true;
-                    quote_char = ch;
-                } else if ch == quote_char {
-                    in_quotes = false;
-                }
-                current.push(ch);
-            },
-            ',' if !in_quotes => {
-                parts.push(current.trim().to_string());
-                current.clear();
-            },
-            _ => {
-                current.push(ch);
-            }
+    let table_name = match get_latest_table_for_server(pool, server.id).await? {
+        Some(table_name) => table_name,
+        None => return Ok(Vec::new()), // No tables available for this server
+    };
+
+    let map_size = server.map_size_or(default_map_size);
+    let distance = toroidal_distance_squared_sql("$2", "$3", "$5");
+
+    // Searches a growing circle so the bounding box keeps the scan small. Once k villages
+    // lie within `radius`, every village outside it is farther away than all of them, so
+    // the answer is final. A radius of map_size covers every tile.
+    let mut radius = NEAREST_INITIAL_RADIUS.min(map_size);
+    loop {
+        let bounded = radius < map_size;
+        let search_area = if bounded {
+            format!("{} AND {} <= {}", bounding_box_sql(x, y, radius, map_size), distance, radius as i64 * radius as i64)
+        } else {
+            String::new()
+        };
+
+        let query = format!(
+            r#"
+            SELECT id, village, x, y, population, player, alliance, worldid, tid
+            FROM {}
+            WHERE server_id = $1{}
+            AND ($6 OR x <> $2 OR y <> $3)
+            ORDER BY {}, population DESC, id
+            LIMIT $4
+            "#,
+            table_name,
+            search_area,
+            distance
+        );
+
+        let rows = sqlx::query(&query)
+            .bind(server.id)
+            .bind(x)
+            .bind(y)
+            .bind(k)
+            .bind(map_size)
+            .bind(include_self)
+            .fetch_all(pool)
+            .await?;
+
+        if !bounded || rows.len() as i64 >= k {
+            let villages: Vec<MapData> = rows
+                .iter()
+                .map(MapData::from_row)
+                .collect::<Result<_, _>>()?;
+            return Ok(villages);
         }
+
+        radius = (radius * 2).min(map_size);
     }
-    if !current.is_empty() {
-        parts.push(current.trim().to_string());
-    }
-    
-    // Ensure we have at least the minimum required fields
-    if parts.len() < 11 {
-        return Err(anyhow::anyhow!("Not enough values in x_world record"));
-    }
-    
-    // Parse the values according to the x_world format
-    let worldid = parts[0].parse::<i32>().ok();
-    let x = parts[1].parse::<i32>().unwrap_or(0);
-    let y = parts[2].parse::<i32>().unwrap_or(0);
-    let tid = parts[3].parse::<i32>().ok();
-    let vid = parts[4].parse::<i32>().ok();
-    
-    // Clean village name (remove quotes)
-    let village = parts[5].trim_matches('\'').trim_matches('"').to_string();
-    
-    let uid = parts[6].parse::<i32>().ok();
-    
-    // Clean player name (remove quotes)
-    let player = if parts[7] == "NULL" || parts[7].is_empty() {
-        None
-    } else {
-        Some(parts[7].trim_matches('\'').trim_matches('"').to_string())
+}
+
+pub async fn get_villages_in_box(
+    pool: &PgPool,
+    x_min: i32,
+    x_max: i32,
+    y_min: i32,
+    y_max: i32,
+    include_nature: bool,
+) -> Result<Vec<MapData>> {
+    // Get the active server
+    let active_server = get_active_server(pool).await?;
+
+    let server = match active_server {
+        Some(server) => server,
+        None => return Ok(Vec::new()), // No active server
     };
-    
-    let aid = parts[8].parse::<i32>().ok();
-    
-    // Clean alliance name (remove quotes)
-    let alliance = if parts.len() > 9 && parts[9] != "NULL" && !parts[9].is_empty() {
-        Some(parts[9].trim_matches('\'').trim_matches('"').to_string())
-    } else {
-        None
+
+    let table_name = match get_latest_table_for_server(pool, server.id).await? {
+        Some(table_name) => table_name,
+        None => return Ok(Vec::new()), // No tables available for this server
     };
-    
-    // Parse population (usually around index 10, but can vary)
-    let population = parts[10].parse::<i32>().unwrap_or(0);
-    
-    Ok(ParsedVillage {
-        worldid,
-        x,
-        y,
-        tid,
-        vid,
-        village,
-        uid,
-        player,
-        aid,
-        alliance,
-        population,
-    })
-}
 
-async fn insert_parsed_village_to_table_with_server(pool: &PgPool, village: ParsedVillage, table_name: &str, server_id: i32) -> Result<()> {
+    // Plain range conditions so the (server_id, x, y) coordinate index can be used
     let query = format!(
         r#"
-        INSERT INTO {} (server_id, worldid, x, y, tid, vid, village, uid, player, aid, alliance, population)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        SELECT id, village, x, y, population, player, alliance, worldid, tid
+        FROM {}
+        WHERE server_id = $1
+        AND x BETWEEN $2 AND $3
+        AND y BETWEEN $4 AND $5{}
+        ORDER BY population DESC
         "#,
-        table_name
+        table_name,
+        nature_condition(include_nature)
     );
-    
-    sqlx::query(&query)
-        .bind(server_id)
-        .bind(village.worldid)
-        .bind(village.x)
-        .bind(village.y)
-        .bind(village.tid)
-        .bind(village.vid)
-        .bind(village.village)
-        .bind(village.uid)
-        .bind(village.player)
-        .bind(village.aid)
-        .bind(village.alliance)
-        .bind(village.population)
-        .execute(pool)
+
+    let rows = sqlx::query(&query)
+        .bind(server.id)
+        .bind(x_min)
+        .bind(x_max)
+        .bind(y_min)
+        .bind(y_max)
+        .fetch_all(pool)
         .await?;
-    
-    Ok(())
+
+    let villages: Vec<MapData> = rows
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(villages)
 }
 
-// Server management functions
-pub async fn get_all_servers(pool: &PgPool) -> Result<Vec<Server>> {
-    let rows = sqlx::query("SELECT id, name, url, is_active FROM servers ORDER BY name")
+// Every nature village in the server's latest snapshot, for adventure and animal-clearing planning
+pub async fn get_oases(pool: &PgPool, server_id: i32) -> Result<Vec<MapData>> {
+    let Some(table_name) = get_latest_table_for_server(pool, server_id).await? else {
+        return Ok(Vec::new());
+    };
+
+    let query = format!(
+        "SELECT id, village, x, y, population, player, alliance, worldid, tid FROM {} WHERE server_id = $1 AND tid = $2 ORDER BY x, y",
+        table_name
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .bind(NATURE_TRIBE_ID)
         .fetch_all(pool)
         .await?;
 
-    let servers: Vec<Server> = rows
-        .into_iter()
-        .map(|row| Server {
-            id: row.get("id"),
-            name: row.get("name"),
-            url: row.get("url"),
-            is_active: row.get("is_active"),
-        })
-        .collect();
+    let villages: Vec<MapData> = rows
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
 
-    Ok(servers)
+    Ok(villages)
 }
 
-pub async fn add_server(pool: &PgPool, name: &str, url: &str) -> Result<Server> {
-    let row = sqlx::query(
-        "INSERT INTO servers (name, url, is_active) VALUES ($1, $2, $3) RETURNING id, name, url, is_active"
-    )
-    .bind(name)
-    .bind(url)
-    .bind(false) // New servers are not active by default
-    .fetch_one(pool)
-    .await?;
+#[derive(Default)]
+pub struct VillageFilter {
+    pub player: Option<String>,
+    pub alliance: Option<String>,
+    pub tribe: Option<i32>,
+    pub population_range: Option<(i32, i32)>,
+    pub include_nature: bool,
+}
 
-    let server = Server {
-        id: row.get("id"),
-        name: row.get("name"),
-        url: row.get("url"),
-        is_active: row.get("is_active"),
-    };
+#[derive(Clone, Copy)]
+pub enum VillageSort {
+    PopulationAsc,
+    PopulationDesc,
+    X,
+    Y,
+    Name,
+}
 
-    // If this is the first server, make it active and auto-load data
-    let all_servers = get_all_servers(pool).await?;
-    if all_servers.len() == 1 {
-        set_active_server(pool, server.id).await?;
-        
-        // Auto-load data for the new active server
-        match auto_load_data_for_server(pool, &server).await {
-            Ok(load_message) => {
-                println!("Auto-loaded data for new server '{}': {}", server.name, load_message);
-            },
-            Err(e) => {
-                println!("Failed to auto-load data for new server '{}': {}", server.name, e);
-            }
+impl VillageSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "population_asc" => Some(VillageSort::PopulationAsc),
+            "population_desc" => Some(VillageSort::PopulationDesc),
+            "x" => Some(VillageSort::X),
+            "y" => Some(VillageSort::Y),
+            "name" => Some(VillageSort::Name),
+            _ => None,
         }
     }
 
-    Ok(server)
+    // ORDER BY clauses are only ever built from this fixed set, never from user input.
+    // The id tie-breaker keeps page boundaries stable between requests.
+    fn order_by_clause(self) -> &'static str {
+        match self {
+            VillageSort::PopulationAsc => " ORDER BY population ASC, id",
+            VillageSort::PopulationDesc => " ORDER BY population DESC, id",
+            VillageSort::X => " ORDER BY x, y, id",
+            VillageSort::Y => " ORDER BY y, x, id",
+            VillageSort::Name => " ORDER BY village, id",
+        }
+    }
 }
 
-pub async fn set_active_server(pool: &PgPool, server_id: i32) -> Result<()> {
-    // First, set all servers to inactive
-    sqlx::query("UPDATE servers SET is_active = FALSE")
-        .execute(pool)
-        .await?;
-    
-    // Then set the specified server as active
-    sqlx::query("UPDATE servers SET is_active = TRUE WHERE id = $1")
-        .bind(server_id)
-        .execute(pool)
-        .await?;
-    
-    Ok(())
+pub struct Pagination {
+    pub limit: i64,
+    pub offset: i64,
 }
 
-pub async fn set_active_server_with_auto_load(pool: &PgPool, server_id: i32) -> Result<String> {
-    // First activate the server
-    set_active_server(pool, server_id).await?;
-    
-    // Get the server details for auto-loading
-    let servers = get_all_servers(pool).await?;
-    if let Some(server) = servers.into_iter().find(|s| s.id == server_id) {
-        // Auto-load data if needed
-        match auto_load_data_for_server(pool, &server).await {
-            Ok(load_message) => Ok(load_message),
-            Err(e) => Ok(format!("Server activated but failed to auto-load data: {}", e))
-        }
-    } else {
-        Ok("Server activated successfully".to_string())
-    }
+pub struct VillagePage {
+    pub villages: Vec<MapData>,
+    pub total: i64,
 }
 
-pub async fn remove_server(pool: &PgPool, server_id: i32) -> Result<()> {
-    // First, check if this server is currently active
-    let active_server = get_active_server(pool).await?;
-    let is_removing_active = active_server.map_or(false, |server| server.id == server_id);
-    
-    // Get all available dates for this server to clean up data tables
-    let available_dates = get_available_dates_for_server(pool, server_id).await?;
-    
-    // Drop all data tables for this server
-    for (date, _) in available_dates {
-        let table_name = get_table_name_for_server_and_date(server_id, date);
-        let drop_query = format!("DROP TABLE IF EXISTS {}", table_name);
-        sqlx::query(&drop_query).execute(pool).await?;
-        println!("Dropped table: {}", table_name);
+fn push_village_filter<'a>(query: &mut QueryBuilder<'a, Postgres>, server_id: i32, filter: &'a VillageFilter) {
+    query.push(" WHERE server_id = ").push_bind(server_id);
+
+    if let Some(player) = &filter.player {
+        query.push(" AND player = ").push_bind(player);
     }
-    
-    // Remove the server from the servers table
-    sqlx::query("DELETE FROM servers WHERE id = $1")
-        .bind(server_id)
-        .execute(pool)
-        .await?;
-    
-    // If we removed the active server, set another server as active (if any exist)
-    if is_removing_active {
-        let remaining_servers = get_all_servers(pool).await?;
-        if let Some(first_server) = remaining_servers.first() {
-            set_active_server(pool, first_server.id).await?;
-            println!("Set server '{}' as active after removing the active server", first_server.name);
-        }
+
+    if let Some(alliance) = &filter.alliance {
+        // Villages without an alliance are stored as NULL or '' and never match a tag
+        query.push(" AND alliance IS NOT NULL AND alliance != '' AND alliance = ").push_bind(alliance);
     }
-    
-    Ok(())
-}
 
-pub async fn get_latest_data_date_for_server(pool: &PgPool, server_id: i32) -> Result<Option<chrono::NaiveDate>> {
-    let available_dates = get_available_dates_for_server(pool, server_id).await?;
-    
-    if available_dates.is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(available_dates[0].0)) // Dates are sorted DESC, so first is latest
+    // Asking for tribe 4 explicitly returns nature villages regardless of include_nature
+    match filter.tribe {
+        Some(tribe) => {
+            query.push(" AND tid = ").push_bind(tribe);
+        },
+        None if !filter.include_nature => {
+            query.push(" AND tid IS DISTINCT FROM ").push_bind(NATURE_TRIBE_ID);
+        },
+        None => {},
     }
-}
 
-pub async fn is_new_data_needed_for_server(pool: &PgPool, server_id: i32) -> Result<bool> {
-    let today = chrono::Utc::now().date_naive();
-    
-    match get_latest_data_date_for_server(pool, server_id).await? {
-        Some(latest_date) => Ok(latest_date < today),
-        None => Ok(true), // No data exists, so we need to load it
+    if let Some((min_population, max_population)) = filter.population_range {
+        query
+            .push(" AND population BETWEEN ")
+            .push_bind(min_population)
+            .push(" AND ")
+            .push_bind(max_population);
     }
 }
 
-pub async fn auto_load_data_for_server(pool: &PgPool, server: &Server) -> Result<String> {
-    // Check if new data is needed
-    if !is_new_data_needed_for_server(pool, server.id).await? {
-        return Ok("Data is up to date".to_string());
-    }
+pub async fn get_filtered_villages(
+    pool: &PgPool,
+    filter: &VillageFilter,
+    sort: Option<VillageSort>,
+    page: Pagination,
+) -> Result<VillagePage> {
+    let _timer = QueryTimer::start("filtered_villages");
+    let empty_page = VillagePage { villages: Vec::new(), total: 0 };
 
-    // Construct the SQL URL based on the server URL
-    let sql_url = if server.url.ends_with("/map.sql") || server.url.ends_with("map.sql") {
-        server.url.clone()
-    } else {
-        format!("{}/map.sql", server.url.trim_end_matches('/'))
+    // Get the active server
+    let active_server = get_active_server(pool).await?;
+
+    let server = match active_server {
+        Some(server) => server,
+        None => return Ok(empty_page), // No active server
     };
-    
-    println!("Auto-loading data for server '{}' from: {}", server.name, sql_url);
 
-    // Fetch the SQL file from the URL
-    let client = reqwest::Client::new();
-    let response = client.get(&sql_url).send().await
-        .map_err(|e| anyhow::anyhow!("Failed to fetch SQL from {}: {}", sql_url, e))?;
+    let table_name = match get_latest_table_for_server(pool, server.id).await? {
+        Some(table_name) => table_name,
+        None => return Ok(empty_page), // No tables available for this server
+    };
 
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!("HTTP error {}: Failed to fetch SQL from {}", response.status(), sql_url));
-    }
+    let mut count_query = QueryBuilder::<Postgres>::new(format!("SELECT COUNT(*) FROM {}", table_name));
+    push_village_filter(&mut count_query, server.id, filter);
+    let total: i64 = count_query.build_query_scalar().fetch_one(pool).await?;
 
-    let sql_content = response.text().await
-        .map_err(|e| anyhow::anyhow!("Failed to read SQL response: {}", e))?;
+    let mut query = QueryBuilder::<Postgres>::new(format!(
+        "SELECT id, village, x, y, population, player, alliance, worldid, tid FROM {}",
+        table_name
+    ));
+    push_village_filter(&mut query, server.id, filter);
 
-    // Execute the SQL for this specific server
-    let count = execute_sql_for_server(pool, &sql_content, server.id).await?;
-    
-    Ok(format!("Successfully loaded {} villages for server '{}'", count, server.name))
+    // Range queries are used for farm finding, so by default the smallest villages come first
+    let sort = sort.unwrap_or(if filter.population_range.is_some() {
+        VillageSort::PopulationAsc
+    } else {
+        VillageSort::PopulationDesc
+    });
+    query.push(sort.order_by_clause());
+
+    query.push(" LIMIT ").push_bind(page.limit);
+    query.push(" OFFSET ").push_bind(page.offset);
+
+    let rows = query.build().fetch_all(pool).await?;
+
+    let villages: Vec<MapData> = rows
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(VillagePage { villages, total })
 }
 
-pub async fn get_active_server(pool: &PgPool) -> Result<Option<Server>> {
-    let row = sqlx::query("SELECT id, name, url, is_active FROM servers WHERE is_active = TRUE LIMIT 1")
-        .fetch_optional(pool)
-        .await?;
+pub async fn get_top_villages(pool: &PgPool, n: i64) -> Result<Vec<MapData>> {
+    let page = Pagination { limit: n, offset: 0 };
+    let top = get_filtered_villages(pool, &VillageFilter::default(), Some(VillageSort::PopulationDesc), page).await?;
+    Ok(top.villages)
+}
 
-    if let Some(row) = row {
-        Ok(Some(Server {
-            id: row.get("id"),
-            name: row.get("name"),
-            url: row.get("url"),
-            is_active: row.get("is_active"),
-        }))
-    } else {
-        Ok(None)
+// Escapes the LIKE wildcards (and the escape character itself) so user input
+// is matched literally inside an ILIKE pattern
+fn escape_like_pattern(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for ch in input.chars() {
+        if matches!(ch, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
     }
+    escaped
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct TribeStats {
-    pub tribe_id: i32,
-    pub tribe_name: String,
-    pub village_count: i32,
-    pub total_population: i64,
-}
+pub async fn search_villages_by_name(pool: &PgPool, query: &str, limit: i64) -> Result<Vec<MapData>> {
+    // Get the active server
+    let active_server = get_active_server(pool).await?;
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PlayerStats {
-    pub player_name: String,
-    pub village_count: i32,
-    pub total_population: i64,
-    pub alliance: Option<String>,
-    pub profile_link: Option<String>,
-    pub alliance_link: Option<String>,
+    let server = match active_server {
+        Some(server) => server,
+        None => return Ok(Vec::new()), // No active server
+    };
+
+    let table_name = match get_latest_table_for_server(pool, server.id).await? {
+        Some(table_name) => table_name,
+        None => return Ok(Vec::new()), // No tables available for this server
+    };
+
+    let search_query = format!(
+        r#"
+        SELECT id, village, x, y, population, player, alliance, worldid, tid
+        FROM {}
+        WHERE server_id = $1 AND village ILIKE $2 ESCAPE '\'
+        ORDER BY population DESC
+        LIMIT $3
+        "#,
+        table_name
+    );
+
+    let rows = sqlx::query(&search_query)
+        .bind(server.id)
+        .bind(format!("%{}%", escape_like_pattern(query)))
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    let villages: Vec<MapData> = rows
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(villages)
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct WorldInfo {
-    pub tribe_stats: Vec<TribeStats>,
-    pub top_players: Vec<PlayerStats>,
-    pub total_villages: i32,
-    pub total_population: i64,
+async fn column_exists(pool: &PgPool, table_name: &str, column_name: &str) -> Result<bool> {
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT FROM information_schema.columns WHERE table_schema = 'public' AND table_name = $1 AND column_name = $2)"
+    )
+    .bind(table_name)
+    .bind(column_name)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
+}
+
+async fn table_exists(pool: &PgPool, table_name: &str) -> Result<bool> {
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT FROM information_schema.tables WHERE table_schema = 'public' AND table_name = $1)"
+    )
+    .bind(table_name)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
 }
 
 #[derive(Serialize, Deserialize, Clone)]
-pub struct AfkVillage {
-    pub village_name: String,
+pub struct OwnershipChange {
     pub x: i32,
     pub y: i32,
-    pub population: i32,
-    pub player_name: String,
-    pub alliance: Option<String>,
-    pub days_without_growth: i32,
+    pub village_name: String,
+    pub old_player: Option<String>,
+    pub new_player: Option<String>,
+    pub old_alliance: Option<String>,
+    pub new_alliance: Option<String>,
+    pub old_population: i32,
+    pub new_population: i32,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct AllianceStats {
-    pub alliance_name: String,
-    pub alliance_id: Option<i32>,
-    pub member_count: i32,
-    pub village_count: i32,
-    pub total_population: i64,
-    pub average_population_per_village: i32,
-    pub population_growth: i64,
-    pub growth_percentage: f64,
-    pub alliance_link: Option<String>,
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    pub from: chrono::NaiveDate,
+    pub to: chrono::NaiveDate,
+    pub conquered: Vec<OwnershipChange>,
+    pub founded: Vec<MapData>,
+    pub abandoned: Vec<MapData>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct AllianceInfo {
-    pub top_alliances: Vec<AllianceStats>,
-    pub total_alliances: i32,
+// Resolves the dated table for a snapshot of a server, failing with a descriptive
+// error when that snapshot was never loaded (or has been cleaned up)
+pub async fn get_snapshot_table(pool: &PgPool, server_id: i32, date: chrono::NaiveDate) -> Result<String> {
+    let table_name = get_table_name_for_server_and_date(server_id, date);
+
+    if !table_exists(pool, &table_name).await? {
+        return Err(SnapshotNotFound { server_id, date }.into());
+    }
+
+    Ok(table_name)
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct AfkSearchParams {
-    pub quadrant: String, // "NE", "SE", "SW", "NW"
-    pub days: i32, // 1-10
+async fn get_snapshot_tables(pool: &PgPool, server_id: i32, from: chrono::NaiveDate, to: chrono::NaiveDate) -> Result<(String, String)> {
+    let from_table = get_snapshot_table(pool, server_id, from).await?;
+    let to_table = get_snapshot_table(pool, server_id, to).await?;
+
+    Ok((from_table, to_table))
 }
 
-fn get_tribe_name(tribe_id: i32) -> String {
-    match tribe_id {
-        1 => "Romans".to_string(),
-        2 => "Teutons".to_string(),
-        3 => "Gauls".to_string(),
-        4 => "Nature".to_string(),
-        5 => "Natars".to_string(),
-        6 => "Egyptians".to_string(),
-        7 => "Huns".to_string(),
-        _ => format!("Tribe {}", tribe_id),
-    }
+#[derive(Debug)]
+pub struct SnapshotNotFound {
+    pub server_id: i32,
+    pub date: chrono::NaiveDate,
 }
 
-pub async fn get_world_info(pool: &PgPool) -> Result<WorldInfo> {
-    // Get the active server
-    let active_server = get_active_server(pool).await?;
-    
-    if let Some(server) = active_server {
-        get_world_info_for_server(pool, server.id).await
-    } else {
-        Err(anyhow::anyhow!("No active server found"))
+impl std::fmt::Display for SnapshotNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No snapshot for server {} on {}", self.server_id, self.date)
     }
 }
 
-pub async fn get_world_info_for_server(pool: &PgPool, server_id: i32) -> Result<WorldInfo> {
-    // Get the latest table for this server
-    let available_dates = get_available_dates_for_server(pool, server_id).await?;
-    
-    if available_dates.is_empty() {
-        return Ok(WorldInfo {
-            tribe_stats: Vec::new(),
-            top_players: Vec::new(),
-            total_villages: 0,
-            total_population: 0,
-        });
-    }
-    
-    let latest_date = available_dates[0].0;
-    let table_name = get_table_name_for_server_and_date(server_id, latest_date);
-    
-    // Check if table exists
-    let table_exists: bool = sqlx::query_scalar(
-        "SELECT EXISTS (SELECT FROM information_schema.tables WHERE table_schema = 'public' AND table_name = $1)"
-    )
-    .bind(&table_name)
-    .fetch_one(pool)
-    .await?;
-    
-    if !table_exists {
-        return Ok(WorldInfo {
-            tribe_stats: Vec::new(),
-            top_players: Vec::new(),
-            total_villages: 0,
-            total_population: 0,
-        });
+impl std::error::Error for SnapshotNotFound {}
+
+// Which villages a snapshot comparison covers
+#[derive(Clone, Copy)]
+enum DiffScope {
+    AllVillages,
+    // Only coordinates on the server's watchlist
+    Watchlist,
+}
+
+impl DiffScope {
+    // Extra WHERE condition on the compared village, selected under `alias`
+    fn filter(self, alias: &str) -> String {
+        match self {
+            DiffScope::AllVillages => String::new(),
+            DiffScope::Watchlist => format!(
+                "AND EXISTS (SELECT 1 FROM watchlist w WHERE w.server_id = {0}.server_id AND w.x = {0}.x AND w.y = {0}.y)",
+                alias
+            ),
+        }
     }
-    
-    // Get the active server for profile links
-    let active_server = get_active_server(pool).await?;
-    let server_base_url = if let Some(server) = &active_server {
-        // Remove /map.sql from the end if present and prepare base URL for profile links
-        let base_url = server.url.trim_end_matches("/map.sql").trim_end_matches("map.sql");
-        Some(base_url.trim_end_matches('/').to_string())
+}
+
+// Coordinates present in both tables whose owning player differs. With `exclude_natars`,
+// villages taken from or handed over to the Natars are left out.
+async fn find_ownership_changes(
+    pool: &PgPool,
+    server_id: i32,
+    from_table: &str,
+    to_table: &str,
+    exclude_natars: bool,
+    scope: DiffScope,
+) -> Result<Vec<OwnershipChange>> {
+    let condition = if exclude_natars {
+        "f.player IS DISTINCT FROM t.player AND f.player IS DISTINCT FROM 'Natars' AND t.player IS DISTINCT FROM 'Natars'"
     } else {
-        None
+        "f.player IS DISTINCT FROM t.player"
     };
-    
-    // Get tribe statistics
-    let tribe_query = format!(
-        "SELECT tid, COUNT(*) as village_count, SUM(population) as total_population 
-         FROM {} 
-         WHERE server_id = $1 AND tid IS NOT NULL 
-         GROUP BY tid 
-         ORDER BY total_population DESC",
-        table_name
+
+    find_changes_between(pool, server_id, from_table, to_table, condition, scope).await
+}
+
+// Coordinates present in both tables where the same player's village grew or shrank
+async fn find_population_changes(
+    pool: &PgPool,
+    server_id: i32,
+    from_table: &str,
+    to_table: &str,
+    scope: DiffScope,
+) -> Result<Vec<OwnershipChange>> {
+    let condition = "f.player IS NOT DISTINCT FROM t.player AND f.population <> t.population";
+    find_changes_between(pool, server_id, from_table, to_table, condition, scope).await
+}
+
+// `condition` compares the row from the `from` snapshot (f) with the one at the same
+// coordinate in the `to` snapshot (t); it is always one of the fixed strings above
+async fn find_changes_between(
+    pool: &PgPool,
+    server_id: i32,
+    from_table: &str,
+    to_table: &str,
+    condition: &str,
+    scope: DiffScope,
+) -> Result<Vec<OwnershipChange>> {
+    let query = format!(
+        r#"
+        SELECT t.x, t.y, t.village, f.player AS old_player, t.player AS new_player,
+               f.alliance AS old_alliance, t.alliance AS new_alliance,
+               f.population AS old_population, t.population AS new_population
+        FROM {} t
+        JOIN {} f ON f.x = t.x AND f.y = t.y AND f.server_id = t.server_id
+        WHERE t.server_id = $1
+        AND {}
+        {}
+        ORDER BY t.population DESC
+        "#,
+        to_table, from_table, condition, scope.filter("t")
     );
-    
-    let tribe_rows = sqlx::query(&tribe_query)
+
+    let rows = sqlx::query(&query)
         .bind(server_id)
         .fetch_all(pool)
         .await?;
-    
-    let tribe_stats: Vec<TribeStats> = tribe_rows
+
+    let changes: Vec<OwnershipChange> = rows
         .into_iter()
-        .map(|row| {
-            let tribe_id: i32 = row.get("tid");
-            TribeStats {
-                tribe_id,
-                tribe_name: get_tribe_name(tribe_id),
-                village_count: row.get::<i64, _>("village_count") as i32,
-                total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
-            }
+        .map(|row| OwnershipChange {
+            x: row.get("x"),
+            y: row.get("y"),
+            village_name: row.get("village"),
+            old_player: row.get("old_player"),
+            new_player: row.get("new_player"),
+            old_alliance: row.get("old_alliance"),
+            new_alliance: row.get("new_alliance"),
+            old_population: row.get("old_population"),
+            new_population: row.get("new_population"),
         })
         .collect();
-    
-    // Get top 10 players by population (excluding Natars)
-    let player_query = format!(
-        "SELECT player, alliance, uid, aid, COUNT(*) as village_count, SUM(population) as total_population 
-         FROM {} 
-         WHERE server_id = $1 AND player IS NOT NULL AND player != '' AND player != 'Natars'
-         GROUP BY player, alliance, uid, aid 
-         ORDER BY total_population DESC 
-         LIMIT 10",
-        table_name
+
+    Ok(changes)
+}
+
+// Villages in `present_table` with no village at the same coordinate in `absent_table`
+async fn find_villages_missing_from(
+    pool: &PgPool,
+    server_id: i32,
+    present_table: &str,
+    absent_table: &str,
+    scope: DiffScope,
+) -> Result<Vec<MapData>> {
+    let query = format!(
+        r#"
+        SELECT p.id, p.village, p.x, p.y, p.population, p.player, p.alliance, p.worldid, p.tid
+        FROM {} p
+        LEFT JOIN {} a ON a.x = p.x AND a.y = p.y AND a.server_id = p.server_id
+        WHERE p.server_id = $1 AND a.id IS NULL
+        {}
+        ORDER BY p.population DESC
+        "#,
+        present_table, absent_table, scope.filter("p")
     );
-    
-    let player_rows = sqlx::query(&player_query)
+
+    let rows = sqlx::query(&query)
         .bind(server_id)
         .fetch_all(pool)
         .await?;
-    
-    let top_players: Vec<PlayerStats> = player_rows
-        .into_iter()
-        .map(|row| {
-            let uid: Option<i32> = row.get("uid");
-            let aid: Option<i32> = row.get("aid");
-            let profile_link = if let (Some(base_url), Some(player_uid)) = (&server_base_url, uid) {
-                Some(format!("{}/profile/{}", base_url, player_uid))
-            } else {
-                None
-            };
-            let alliance_link = if let (Some(base_url), Some(alliance_id)) = (&server_base_url, aid) {
-                Some(format!("{}/alliance/{}", base_url, alliance_id))
-            } else {
-                None
-            };
-            
-            PlayerStats {
-                player_name: row.get("player"),
-                village_count: row.get::<i64, _>("village_count") as i32,
-                total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
-                alliance: row.get("alliance"),
-                profile_link,
-                alliance_link,
-            }
-        })
-        .collect();
-    
-    // Get total statistics
-    let total_query = format!(
-        "SELECT COUNT(*) as total_villages, SUM(population) as total_population 
-         FROM {} 
-         WHERE server_id = $1",
-        table_name
-    );
-    
-    let total_row = sqlx::query(&total_query)
-        .bind(server_id)
-        .fetch_one(pool)
-        .await?;
-    
-    let total_villages = total_row.get::<i64, _>("total_villages") as i32;
-    let total_population = total_row.get::<Option<i64>, _>("total_population").unwrap_or(0);
-    
-    Ok(WorldInfo {
-        tribe_stats,
-        top_players,
-        total_villages,
-        total_population,
+
+    let villages: Vec<MapData> = rows
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(villages)
+}
+
+pub async fn diff_snapshots(pool: &PgPool, server_id: i32, from: chrono::NaiveDate, to: chrono::NaiveDate) -> Result<SnapshotDiff> {
+    let _timer = QueryTimer::start("diff_snapshots");
+    let (from_table, to_table) = get_snapshot_tables(pool, server_id, from, to).await?;
+
+    let conquered = find_ownership_changes(pool, server_id, &from_table, &to_table, false, DiffScope::AllVillages).await?;
+    let founded = find_villages_missing_from(pool, server_id, &to_table, &from_table, DiffScope::AllVillages).await?;
+    let abandoned = find_villages_missing_from(pool, server_id, &from_table, &to_table, DiffScope::AllVillages).await?;
+
+    Ok(SnapshotDiff {
+        from,
+        to,
+        conquered,
+        founded,
+        abandoned,
     })
 }
 
-pub async fn find_afk_villages(pool: &PgPool, params: AfkSearchParams) -> Result<Vec<AfkVillage>> {
-    // Get the active server
-    let active_server = get_active_server(pool).await?;
-    
-    if let Some(server) = active_server {
-        find_afk_villages_for_server(pool, server.id, params).await
-    } else {
-        Err(anyhow::anyhow!("No active server found"))
-    }
+pub async fn find_conquests(
+    pool: &PgPool,
+    server_id: i32,
+    from: chrono::NaiveDate,
+    to: chrono::NaiveDate,
+    exclude_natars: bool,
+) -> Result<Vec<OwnershipChange>> {
+    let _timer = QueryTimer::start("find_conquests");
+    let (from_table, to_table) = get_snapshot_tables(pool, server_id, from, to).await?;
+
+    find_ownership_changes(pool, server_id, &from_table, &to_table, exclude_natars, DiffScope::AllVillages).await
 }
 
-pub async fn find_afk_villages_for_server(pool: &PgPool, server_id: i32, params: AfkSearchParams) -> Result<Vec<AfkVillage>> {
-    let available_dates = get_available_dates_for_server(pool, server_id).await?;
-    
-    if available_dates.len() < (params.days as usize + 1) {
-        return Ok(Vec::new()); // Not enough historical data
+// Villages at coordinates that were empty in the `from` snapshot
+pub async fn find_new_villages(pool: &PgPool, server_id: i32, from: chrono::NaiveDate, to: chrono::NaiveDate) -> Result<Vec<MapData>> {
+    let _timer = QueryTimer::start("find_new_villages");
+    let (from_table, to_table) = get_snapshot_tables(pool, server_id, from, to).await?;
+
+    find_villages_missing_from(pool, server_id, &to_table, &from_table, DiffScope::AllVillages).await
+}
+
+// Villages from the `from` snapshot whose coordinates are empty in the `to` snapshot,
+// as they were last seen
+pub async fn find_abandoned_villages(pool: &PgPool, server_id: i32, from: chrono::NaiveDate, to: chrono::NaiveDate) -> Result<Vec<MapData>> {
+    let _timer = QueryTimer::start("find_abandoned_villages");
+    let (from_table, to_table) = get_snapshot_tables(pool, server_id, from, to).await?;
+
+    find_villages_missing_from(pool, server_id, &from_table, &to_table, DiffScope::AllVillages).await
+}
+
+#[derive(Serialize)]
+pub struct WatchedVillage {
+    pub id: i32,
+    pub server_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub label: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl WatchedVillage {
+    fn from_row(row: &PgRow) -> Self {
+        WatchedVillage {
+            id: row.get("id"),
+            server_id: row.get("server_id"),
+            x: row.get("x"),
+            y: row.get("y"),
+            label: row.get("label"),
+            created_at: row.get("created_at"),
+        }
     }
-    
+}
+
+#[derive(Debug)]
+pub struct AlreadyWatched {
+    pub server_id: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl std::fmt::Display for AlreadyWatched {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}|{}) is already on the watchlist of server {}", self.x, self.y, self.server_id)
+    }
+}
+
+impl std::error::Error for AlreadyWatched {}
+
+pub async fn get_watchlist(pool: &PgPool, server_id: i32) -> Result<Vec<WatchedVillage>> {
+    let rows = sqlx::query("SELECT id, server_id, x, y, label, created_at FROM watchlist WHERE server_id = $1 ORDER BY id")
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(WatchedVillage::from_row).collect())
+}
+
+// Coordinates are checked against the server's map size, as for manually created villages.
+// The tile does not need a village on it, so a spot can be watched for new settlers.
+pub async fn add_to_watchlist(
+    pool: &PgPool,
+    server_id: i32,
+    x: i32,
+    y: i32,
+    label: Option<&str>,
+    default_map_size: i32,
+) -> Result<WatchedVillage> {
+    let (min, max) = geo::coordinate_range(get_map_size_for_server(pool, server_id, default_map_size).await?);
+    if !(min..=max).contains(&x) || !(min..=max).contains(&y) {
+        return Err(CoordinateOutOfRange { x, y, min, max }.into());
+    }
+
+    let row = sqlx::query(
+        "INSERT INTO watchlist (server_id, x, y, label) VALUES ($1, $2, $3, $4) RETURNING id, server_id, x, y, label, created_at"
+    )
+    .bind(server_id)
+    .bind(x)
+    .bind(y)
+    .bind(label)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db_error) if db_error.is_unique_violation() => anyhow::Error::new(AlreadyWatched { server_id, x, y }),
+        _ => e.into(),
+    })?;
+
+    Ok(WatchedVillage::from_row(&row))
+}
+
+// None clears the label
+pub async fn update_watchlist_label(pool: &PgPool, id: i32, label: Option<&str>) -> Result<Option<WatchedVillage>> {
+    let row = sqlx::query("UPDATE watchlist SET label = $2 WHERE id = $1 RETURNING id, server_id, x, y, label, created_at")
+        .bind(id)
+        .bind(label)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(WatchedVillage::from_row))
+}
+
+// Also drops the changes recorded for the entry
+pub async fn remove_from_watchlist(pool: &PgPool, id: i32) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM watchlist WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchChangeKind {
+    // Another player owns the village, as in the snapshot diff's conquered list
+    Conquered,
+    // Same owner, different population
+    Population,
+    // A village appeared on the watched tile
+    Founded,
+    // The village on the watched tile is gone
+    Abandoned,
+}
+
+impl WatchChangeKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "conquered" => Some(WatchChangeKind::Conquered),
+            "population" => Some(WatchChangeKind::Population),
+            "founded" => Some(WatchChangeKind::Founded),
+            "abandoned" => Some(WatchChangeKind::Abandoned),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            WatchChangeKind::Conquered => "conquered",
+            WatchChangeKind::Population => "population",
+            WatchChangeKind::Founded => "founded",
+            WatchChangeKind::Abandoned => "abandoned",
+        }
+    }
+}
+
+// One change at a watched coordinate between two consecutive snapshots. The old_ fields
+// are empty for a founded village and the new_ fields for an abandoned one.
+#[derive(Serialize)]
+pub struct WatchlistChange {
+    pub watch_id: i32,
+    pub server_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub label: Option<String>,
+    pub kind: WatchChangeKind,
+    pub from: chrono::NaiveDate,
+    pub to: chrono::NaiveDate,
+    pub village_name: String,
+    pub old_player: Option<String>,
+    pub new_player: Option<String>,
+    pub old_alliance: Option<String>,
+    pub new_alliance: Option<String>,
+    pub old_population: Option<i32>,
+    pub new_population: Option<i32>,
+    pub detected_at: chrono::DateTime<chrono::Utc>,
+}
+
+// A change found by a load, before it is stored against its watchlist entry
+struct DetectedChange {
+    x: i32,
+    y: i32,
+    kind: WatchChangeKind,
+    village_name: String,
+    old_player: Option<String>,
+    new_player: Option<String>,
+    old_alliance: Option<String>,
+    new_alliance: Option<String>,
+    old_population: Option<i32>,
+    new_population: Option<i32>,
+}
+
+impl DetectedChange {
+    fn between(kind: WatchChangeKind, change: OwnershipChange) -> Self {
+        DetectedChange {
+            x: change.x,
+            y: change.y,
+            kind,
+            village_name: change.village_name,
+            old_player: change.old_player,
+            new_player: change.new_player,
+            old_alliance: change.old_alliance,
+            new_alliance: change.new_alliance,
+            old_population: Some(change.old_population),
+            new_population: Some(change.new_population),
+        }
+    }
+
+    fn founded(village: MapData) -> Self {
+        DetectedChange {
+            x: village.x,
+            y: village.y,
+            kind: WatchChangeKind::Founded,
+            village_name: village.name,
+            old_player: None,
+            new_player: village.player,
+            old_alliance: None,
+            new_alliance: village.alliance,
+            old_population: None,
+            new_population: Some(village.population as i32),
+        }
+    }
+
+    fn abandoned(village: MapData) -> Self {
+        DetectedChange {
+            x: village.x,
+            y: village.y,
+            kind: WatchChangeKind::Abandoned,
+            village_name: village.name,
+            old_player: village.player,
+            new_player: None,
+            old_alliance: village.alliance,
+            new_alliance: None,
+            old_population: Some(village.population as i32),
+            new_population: None,
+        }
+    }
+}
+
+// Compares the watched coordinates of the server's two latest snapshots with the same
+// queries as the snapshot diff and stores what changed. Reloading a day replaces the
+// changes an earlier load recorded for it. Returns how many changes were stored.
+pub async fn record_watchlist_changes(pool: &PgPool, server_id: i32) -> Result<usize> {
+    let _timer = QueryTimer::start("record_watchlist_changes");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    let mut dates = available_dates.iter().map(|(date, _)| *date);
+    let (Some(to), Some(from)) = (dates.next(), dates.next()) else {
+        return Ok(0);
+    };
+
+    let watch_ids: HashMap<(i32, i32), i32> = sqlx::query("SELECT id, x, y FROM watchlist WHERE server_id = $1")
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| ((row.get("x"), row.get("y")), row.get("id")))
+        .collect();
+    if watch_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let (from_table, to_table) = get_snapshot_tables(pool, server_id, from, to).await?;
+    let scope = DiffScope::Watchlist;
+    let mut changes = Vec::new();
+    for change in find_ownership_changes(pool, server_id, &from_table, &to_table, false, scope).await? {
+        changes.push(DetectedChange::between(WatchChangeKind::Conquered, change));
+    }
+    for change in find_population_changes(pool, server_id, &from_table, &to_table, scope).await? {
+        changes.push(DetectedChange::between(WatchChangeKind::Population, change));
+    }
+    for village in find_villages_missing_from(pool, server_id, &to_table, &from_table, scope).await? {
+        changes.push(DetectedChange::founded(village));
+    }
+    for village in find_villages_missing_from(pool, server_id, &from_table, &to_table, scope).await? {
+        changes.push(DetectedChange::abandoned(village));
+    }
+    // An entry removed while the comparison ran has nothing left to record against
+    changes.retain(|change| watch_ids.contains_key(&(change.x, change.y)));
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "DELETE FROM watchlist_changes c USING watchlist w WHERE c.watch_id = w.id AND w.server_id = $1 AND c.to_date = $2"
+    )
+    .bind(server_id)
+    .bind(to)
+    .execute(&mut *tx)
+    .await?;
+
+    for batch in changes.chunks(INSERT_BATCH_SIZE) {
+        let mut query = QueryBuilder::<Postgres>::new(
+            "INSERT INTO watchlist_changes (watch_id, kind, from_date, to_date, village, old_player, new_player, old_alliance, new_alliance, old_population, new_population) "
+        );
+        query.push_values(batch, |mut row, change| {
+            row.push_bind(watch_ids[&(change.x, change.y)])
+                .push_bind(change.kind.as_str())
+                .push_bind(from)
+                .push_bind(to)
+                .push_bind(&change.village_name)
+                .push_bind(&change.old_player)
+                .push_bind(&change.new_player)
+                .push_bind(&change.old_alliance)
+                .push_bind(&change.new_alliance)
+                .push_bind(change.old_population)
+                .push_bind(change.new_population);
+        });
+        query.build().execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+    Ok(changes.len())
+}
+
+pub struct WatchlistChangeFilter {
+    pub since: Option<chrono::NaiveDate>,
+    pub kind: Option<WatchChangeKind>,
+    pub limit: i64,
+}
+
+// Newest snapshot first; `since` keeps changes whose later snapshot is on or after it
+pub async fn get_watchlist_changes(pool: &PgPool, server_id: i32, filter: &WatchlistChangeFilter) -> Result<Vec<WatchlistChange>> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        r#"
+        SELECT c.watch_id, w.server_id, w.x, w.y, w.label, c.kind, c.from_date, c.to_date, c.village,
+               c.old_player, c.new_player, c.old_alliance, c.new_alliance,
+               c.old_population, c.new_population, c.detected_at
+        FROM watchlist_changes c
+        JOIN watchlist w ON w.id = c.watch_id
+        WHERE w.server_id = "#,
+    );
+    query.push_bind(server_id);
+    if let Some(since) = filter.since {
+        query.push(" AND c.to_date >= ").push_bind(since);
+    }
+    if let Some(kind) = filter.kind {
+        query.push(" AND c.kind = ").push_bind(kind.as_str());
+    }
+    query.push(" ORDER BY c.to_date DESC, c.id LIMIT ").push_bind(filter.limit);
+
+    let rows = query.build().fetch_all(pool).await?;
+    rows.iter()
+        .map(|row| {
+            let kind: String = row.get("kind");
+            Ok(WatchlistChange {
+                watch_id: row.get("watch_id"),
+                server_id: row.get("server_id"),
+                x: row.get("x"),
+                y: row.get("y"),
+                label: row.get("label"),
+                kind: WatchChangeKind::parse(&kind).ok_or_else(|| anyhow::anyhow!("Unknown watchlist change kind {:?}", kind))?,
+                from: row.get("from_date"),
+                to: row.get("to_date"),
+                village_name: row.get("village"),
+                old_player: row.get("old_player"),
+                new_player: row.get("new_player"),
+                old_alliance: row.get("old_alliance"),
+                new_alliance: row.get("new_alliance"),
+                old_population: row.get("old_population"),
+                new_population: row.get("new_population"),
+                detected_at: row.get("detected_at"),
+            })
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct MembershipChange {
+    pub player: String,
+    // Size of the player while a member: on `to` for joins, on `from` for departures
+    pub village_count: i64,
+    pub population: i64,
+    // The player's alliance in the other snapshot, i.e. where a new member came from or
+    // where a former member went. None when they had no alliance or no villages there.
+    pub other_alliance: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AllianceMembershipChanges {
+    pub alliance: String,
+    pub from: chrono::NaiveDate,
+    pub to: chrono::NaiveDate,
+    pub joined: Vec<MembershipChange>,
+    pub left: Vec<MembershipChange>,
+}
+
+// Players with villages in the alliance on `to` but not on `from` (joined), and the
+// other way round (left). A player who switched alliances shows up as left in one
+// alliance's changes and joined in the other's.
+pub async fn get_alliance_membership_changes(
+    pool: &PgPool,
+    server_id: i32,
+    alliance: &str,
+    from: chrono::NaiveDate,
+    to: chrono::NaiveDate,
+) -> Result<AllianceMembershipChanges> {
+    let _timer = QueryTimer::start("alliance_membership_changes");
+    let (from_table, to_table) = get_snapshot_tables(pool, server_id, from, to).await?;
+
+    let joined = find_members_missing_from(pool, server_id, alliance, &to_table, &from_table).await?;
+    let left = find_members_missing_from(pool, server_id, alliance, &from_table, &to_table).await?;
+
+    Ok(AllianceMembershipChanges { alliance: alliance.to_string(), from, to, joined, left })
+}
+
+// Members of the alliance in `members_table` who were not members in `other_table`,
+// largest first, with the alliance each belonged to in `other_table`
+async fn find_members_missing_from(
+    pool: &PgPool,
+    server_id: i32,
+    alliance: &str,
+    members_table: &str,
+    other_table: &str,
+) -> Result<Vec<MembershipChange>> {
+    let query = format!(
+        r#"
+        WITH members AS (
+            SELECT player, COUNT(*) AS village_count, SUM(population)::BIGINT AS population
+            FROM {members}
+            WHERE server_id = $1 AND alliance = $2 AND alliance != ''
+            AND player IS NOT NULL AND player != ''
+            GROUP BY player
+        ),
+        other AS (
+            SELECT player,
+                   BOOL_OR(alliance = $2) AS was_member,
+                   MAX(NULLIF(alliance, '')) FILTER (WHERE alliance IS DISTINCT FROM $2) AS alliance
+            FROM {other}
+            WHERE server_id = $1 AND player IS NOT NULL
+            GROUP BY player
+        )
+        SELECT m.player, m.village_count, m.population, o.alliance AS other_alliance
+        FROM members m
+        LEFT JOIN other o ON o.player = m.player
+        WHERE o.was_member IS NOT TRUE
+        ORDER BY m.population DESC, m.player
+        "#,
+        members = members_table,
+        other = other_table,
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .bind(alliance)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| MembershipChange {
+            player: row.get("player"),
+            village_count: row.get("village_count"),
+            population: row.get("population"),
+            other_alliance: row.get("other_alliance"),
+        })
+        .collect())
+}
+
+#[derive(Serialize)]
+pub struct AllianceSwitch {
+    pub uid: i32,
+    // Name on `to`, or on `from` for a player who is gone
+    pub player: String,
+    pub old_alliance: Option<String>,
+    pub new_alliance: Option<String>,
+    // Population on `to`, or on `from` for a player who is gone
+    pub population: i64,
+}
+
+// Players, matched by uid so renames do not count, whose alliance differs between the two
+// snapshots. By default both sides need an alliance; with `include_unaligned`, players
+// who joined from or left to no alliance, or who appeared or vanished, are listed too.
+pub async fn find_alliance_switchers(
+    pool: &PgPool,
+    server_id: i32,
+    from: chrono::NaiveDate,
+    to: chrono::NaiveDate,
+    include_unaligned: bool,
+) -> Result<Vec<AllianceSwitch>> {
+    let _timer = QueryTimer::start("find_alliance_switchers");
+    let (from_table, to_table) = get_snapshot_tables(pool, server_id, from, to).await?;
+
+    let players = |table: &str| {
+        format!(
+            "SELECT uid, MAX(player) AS player, MAX(NULLIF(alliance, '')) AS alliance, SUM(population)::BIGINT AS population
+             FROM {}
+             WHERE server_id = $1 AND uid IS NOT NULL AND {}
+             GROUP BY uid",
+            table,
+            player_filter(false)
+        )
+    };
+    let query = format!(
+        r#"
+        WITH before AS ({}), after AS ({})
+        SELECT COALESCE(a.uid, b.uid) AS uid,
+               COALESCE(a.player, b.player) AS player,
+               b.alliance AS old_alliance,
+               a.alliance AS new_alliance,
+               COALESCE(a.population, b.population) AS population
+        FROM before b
+        FULL JOIN after a ON a.uid = b.uid
+        WHERE b.alliance IS DISTINCT FROM a.alliance
+        AND ($2 OR (b.alliance IS NOT NULL AND a.alliance IS NOT NULL))
+        ORDER BY population DESC, player
+        "#,
+        players(&from_table),
+        players(&to_table)
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .bind(include_unaligned)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| AllianceSwitch {
+            uid: row.get("uid"),
+            player: row.get("player"),
+            old_alliance: row.get("old_alliance"),
+            new_alliance: row.get("new_alliance"),
+            population: row.get("population"),
+        })
+        .collect())
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeBasis {
+    // Rows touched after `since`; only manual edits move updated_at once a load is done
+    UpdatedAt,
+    // Rows that are new or differ from the previous snapshot, since the load itself
+    // rewrote updated_at on every row after `since`
+    PreviousSnapshot,
+}
+
+#[derive(Serialize)]
+pub struct ChangedVillages {
+    pub date: Option<chrono::NaiveDate>,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub basis: ChangeBasis,
+    // The snapshot compared against, for the previous_snapshot basis
+    pub compared_to: Option<chrono::NaiveDate>,
+    pub villages: Vec<MapData>,
+}
+
+// Villages in the latest snapshot that changed after `since`, which defaults to when the
+// previous snapshot was loaded. Every load rewrites updated_at on all rows, so updated_at
+// is only trusted when the latest snapshot was loaded before `since`; otherwise each
+// village is compared with its coordinate in the previous snapshot. Without a previous
+// snapshot every village counts as changed.
+pub async fn get_recently_updated(
+    pool: &PgPool,
+    server_id: i32,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<ChangedVillages> {
+    let _timer = QueryTimer::start("get_recently_updated");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    let mut dates = available_dates.iter().map(|(date, _)| *date);
+    let (Some(date), previous_date) = (dates.next(), dates.next()) else {
+        return Ok(ChangedVillages {
+            date: None,
+            since,
+            basis: ChangeBasis::PreviousSnapshot,
+            compared_to: None,
+            villages: Vec::new(),
+        });
+    };
+    let table_name = get_table_name_for_server_and_date(server_id, date);
+
+    let since = match since {
+        Some(since) => Some(since),
+        None => match previous_date {
+            Some(previous_date) => last_successful_load_at(pool, server_id, previous_date).await?,
+            None => None,
+        },
+    };
+    let loaded_at = last_successful_load_at(pool, server_id, date).await?;
+
+    if let (Some(since), Some(loaded_at)) = (since, loaded_at) {
+        if loaded_at <= since {
+            let query = format!(
+                "SELECT id, village, x, y, population, player, alliance, worldid, tid FROM {} WHERE server_id = $1 AND updated_at > $2 ORDER BY updated_at DESC, id",
+                table_name
+            );
+            let rows = sqlx::query(&query).bind(server_id).bind(since).fetch_all(pool).await?;
+
+            return Ok(ChangedVillages {
+                date: Some(date),
+                since: Some(since),
+                basis: ChangeBasis::UpdatedAt,
+                compared_to: None,
+                villages: rows.iter().map(MapData::from_row).collect::<Result<_, _>>()?,
+            });
+        }
+    }
+
+    let rows = match previous_date {
+        Some(previous_date) => {
+            let query = format!(
+                r#"
+                SELECT t.id, t.village, t.x, t.y, t.population, t.player, t.alliance, t.worldid, t.tid
+                FROM {} t
+                LEFT JOIN {} p ON p.server_id = $1 AND p.x = t.x AND p.y = t.y
+                WHERE t.server_id = $1
+                AND (t.village, t.population, t.player, t.alliance, t.tid, t.vid)
+                    IS DISTINCT FROM (p.village, p.population, p.player, p.alliance, p.tid, p.vid)
+                ORDER BY t.population DESC, t.id
+                "#,
+                table_name,
+                get_table_name_for_server_and_date(server_id, previous_date)
+            );
+            sqlx::query(&query).bind(server_id).fetch_all(pool).await?
+        },
+        None => {
+            let query = format!(
+                "SELECT id, village, x, y, population, player, alliance, worldid, tid FROM {} WHERE server_id = $1 ORDER BY population DESC, id",
+                table_name
+            );
+            sqlx::query(&query).bind(server_id).fetch_all(pool).await?
+        }
+    };
+
+    Ok(ChangedVillages {
+        date: Some(date),
+        since,
+        basis: ChangeBasis::PreviousSnapshot,
+        compared_to: previous_date,
+        villages: rows.iter().map(MapData::from_row).collect::<Result<_, _>>()?,
+    })
+}
+
+async fn last_successful_load_at(
+    pool: &PgPool,
+    server_id: i32,
+    date: chrono::NaiveDate,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+    let loaded_at = sqlx::query_scalar(
+        "SELECT MAX(loaded_at) FROM load_history WHERE server_id = $1 AND date = $2 AND status = 'success'"
+    )
+    .bind(server_id)
+    .bind(date)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(loaded_at)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VillageHistoryPoint {
+    pub date: chrono::NaiveDate,
+    pub population: i32,
+    pub village_name: String,
+    pub player: Option<String>,
+    pub alliance: Option<String>,
+}
+
+pub async fn get_village_history(pool: &PgPool, server_id: i32, x: i32, y: i32) -> Result<Vec<VillageHistoryPoint>> {
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let mut history = Vec::new();
+
+    // Dates are sorted DESC; walk them oldest-first so the series reads chronologically
+    for (date, _) in available_dates.into_iter().rev() {
+        let table_name = get_table_name_for_server_and_date(server_id, date);
+        let query = format!(
+            "SELECT village, population, player, alliance FROM {} WHERE server_id = $1 AND x = $2 AND y = $3 LIMIT 1",
+            table_name
+        );
+
+        let row = sqlx::query(&query)
+            .bind(server_id)
+            .bind(x)
+            .bind(y)
+            .fetch_optional(pool)
+            .await?;
+
+        // Only dates with a village at this coordinate are included
+        if let Some(row) = row {
+            history.push(VillageHistoryPoint {
+                date,
+                population: row.get("population"),
+                village_name: row.get("village"),
+                player: row.get("player"),
+                alliance: row.get("alliance"),
+            });
+        }
+    }
+
+    Ok(history)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlayerHistoryPoint {
+    pub date: chrono::NaiveDate,
+    pub total_population: i64,
+    pub village_count: i32,
+}
+
+pub async fn get_player_population_series(pool: &PgPool, server_id: i32, player: &str, num_days: usize) -> Result<Vec<PlayerHistoryPoint>> {
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let mut series = Vec::new();
+
+    // Take the most recent `num_days` snapshots and return them oldest-first
+    for (date, _) in available_dates.into_iter().take(num_days).rev() {
+        let table_name = get_table_name_for_server_and_date(server_id, date);
+        let query = format!(
+            "SELECT COUNT(*) as village_count, SUM(population) as total_population FROM {} WHERE server_id = $1 AND player = $2",
+            table_name
+        );
+
+        let row = sqlx::query(&query)
+            .bind(server_id)
+            .bind(player)
+            .fetch_one(pool)
+            .await?;
+
+        // Skip snapshots where the player had no villages (not yet registered or deleted)
+        let village_count = row.get::<i64, _>("village_count") as i32;
+        if village_count > 0 {
+            series.push(PlayerHistoryPoint {
+                date,
+                total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
+                village_count,
+            });
+        }
+    }
+
+    Ok(series)
+}
+
+// Default window of the inactive players search, matching the AFK search; a player's
+// timeline status uses the same window
+pub const DEFAULT_INACTIVE_DAYS: usize = 3;
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FirstSeenBasis {
+    // The earliest first_seen of the player's villages in the oldest snapshot they are in
+    Village,
+    // That snapshot's own date, when its villages predate first_seen tracking
+    Snapshot,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerStatus {
+    Active,
+    // In each of the last DEFAULT_INACTIVE_DAYS + 1 snapshots without ever growing, as
+    // the inactive players search counts it
+    Inactive,
+    // Not in the latest snapshot: deleted, renamed, or lost every village
+    Gone,
+}
+
+#[derive(Serialize)]
+pub struct PlayerTimeline {
+    pub player_name: String,
+    pub first_seen: chrono::NaiveDate,
+    pub first_seen_basis: FirstSeenBasis,
+    // The latest snapshot with the player in it
+    pub last_seen: chrono::NaiveDate,
+    pub status: PlayerStatus,
+    // Every stored snapshot the player is in, oldest first
+    pub points: Vec<PlayerHistoryPoint>,
+}
+
+// When a player showed up and how their account developed since. first_seen survives
+// snapshot cleanup because loads carry it over per village, so for an account older than
+// the stored snapshots it still dates their oldest village. A village taken over keeps
+// the date it was founded, so a player whose oldest snapshot only shows conquests looks
+// older than they are. None when no stored snapshot has the player.
+pub async fn get_player_timeline(pool: &PgPool, server_id: i32, player: &str) -> Result<Option<PlayerTimeline>> {
+    let _timer = QueryTimer::start("player_timeline");
+    let points = get_player_population_series(pool, server_id, player, usize::MAX).await?;
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Ok(None);
+    };
+
+    let first_table = get_table_name_for_server_and_date(server_id, first.date);
+    let village_first_seen: Option<chrono::NaiveDate> = if column_exists(pool, &first_table, "first_seen").await? {
+        let query = format!("SELECT MIN(first_seen) FROM {} WHERE server_id = $1 AND player = $2", first_table);
+        sqlx::query_scalar(&query).bind(server_id).bind(player).fetch_one(pool).await?
+    } else {
+        None
+    };
+    let (first_seen, first_seen_basis) = match village_first_seen {
+        Some(date) if date <= first.date => (date, FirstSeenBasis::Village),
+        _ => (first.date, FirstSeenBasis::Snapshot),
+    };
+
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    let latest_date = available_dates.first().map(|(date, _)| *date);
+    let status = if latest_date != Some(last.date) {
+        PlayerStatus::Gone
+    } else {
+        // The player must be in each of the latest snapshots, not just have that many points
+        let window: Vec<chrono::NaiveDate> = available_dates
+            .iter()
+            .take(DEFAULT_INACTIVE_DAYS + 1)
+            .rev()
+            .map(|(date, _)| *date)
+            .collect();
+        let recent = &points[points.len().saturating_sub(window.len())..];
+        let in_every_snapshot = window.len() == DEFAULT_INACTIVE_DAYS + 1
+            && recent.iter().map(|point| point.date).eq(window.iter().copied());
+
+        if in_every_snapshot && recent.windows(2).all(|pair| pair[1].total_population <= pair[0].total_population) {
+            PlayerStatus::Inactive
+        } else {
+            PlayerStatus::Active
+        }
+    };
+
+    Ok(Some(PlayerTimeline {
+        player_name: player.to_string(),
+        first_seen,
+        first_seen_basis,
+        last_seen: last.date,
+        status,
+        points,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct SettlementPoint {
+    pub date: chrono::NaiveDate,
+    // Villages at coordinates that were empty the day before
+    pub new_villages: i64,
+}
+
+// New villages per day over the most recent `num_days` snapshots, oldest first. Each
+// snapshot is compared with the one from the day before; snapshots without one are left
+// out, since comparing across a gap would merge several days of settling into one.
+pub async fn get_settlement_rate(pool: &PgPool, server_id: i32, num_days: usize) -> Result<Vec<SettlementPoint>> {
+    let _timer = QueryTimer::start("settlement_rate");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let mut series = Vec::new();
+
+    for (date, _) in available_dates.iter().take(num_days).rev() {
+        let Some(previous_date) = date.pred_opt() else {
+            continue;
+        };
+        if !available_dates.iter().any(|(available, _)| *available == previous_date) {
+            continue;
+        }
+
+        let query = format!(
+            r#"
+            SELECT COUNT(*)
+            FROM {} p
+            LEFT JOIN {} a ON a.x = p.x AND a.y = p.y AND a.server_id = p.server_id
+            WHERE p.server_id = $1 AND a.id IS NULL
+            "#,
+            get_table_name_for_server_and_date(server_id, *date),
+            get_table_name_for_server_and_date(server_id, previous_date)
+        );
+        let new_villages: i64 = sqlx::query_scalar(&query).bind(server_id).fetch_one(pool).await?;
+
+        series.push(SettlementPoint { date: *date, new_villages });
+    }
+
+    Ok(series)
+}
+
+#[derive(Serialize)]
+pub struct PopulationTrendPoint {
+    pub date: chrono::NaiveDate,
+    pub total_population: i64,
+    pub village_count: i64,
+}
+
+// Server totals for each of the most recent `num_days` snapshots, oldest first. Counts
+// every village, like get_summary_stats, so the last point matches the summary.
+pub async fn get_population_trend(pool: &PgPool, server_id: i32, num_days: usize) -> Result<Vec<PopulationTrendPoint>> {
+    let _timer = QueryTimer::start("population_trend");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let mut series = Vec::new();
+
+    for (date, _) in available_dates.into_iter().take(num_days).rev() {
+        let query = format!(
+            "SELECT COUNT(*) as village_count, COALESCE(SUM(population), 0) as total_population FROM {} WHERE server_id = $1",
+            get_table_name_for_server_and_date(server_id, date)
+        );
+        let row = sqlx::query(&query).bind(server_id).fetch_one(pool).await?;
+
+        series.push(PopulationTrendPoint {
+            date,
+            total_population: row.get("total_population"),
+            village_count: row.get("village_count"),
+        });
+    }
+
+    Ok(series)
+}
+
+#[derive(Debug)]
+pub struct CoordinateOccupied {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl std::fmt::Display for CoordinateOccupied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "A village already exists at ({}|{})", self.x, self.y)
+    }
+}
+
+impl std::error::Error for CoordinateOccupied {}
+
+#[derive(Debug)]
+pub struct CoordinateOutOfRange {
+    pub x: i32,
+    pub y: i32,
+    pub min: i32,
+    pub max: i32,
+}
+
+impl std::fmt::Display for CoordinateOutOfRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "({}|{}) is off the map; x and y must be between {} and {}",
+            self.x, self.y, self.min, self.max
+        )
+    }
+}
+
+impl std::error::Error for CoordinateOutOfRange {}
+
+pub struct NewVillage<'a> {
+    pub name: &'a str,
+    pub x: i32,
+    pub y: i32,
+    pub population: u32,
+}
+
+pub async fn add_village(pool: &PgPool, village: &NewVillage<'_>, default_map_size: i32) -> Result<MapData> {
+    let (server_id, table_name) = get_manual_village_table(pool, [(village.x, village.y)], default_map_size).await?;
+    let mut conn = pool.acquire().await?;
+    insert_manual_village(&mut conn, &table_name, server_id, village).await
+}
+
+// All or nothing: the first village that cannot be inserted rolls back the whole batch
+pub async fn add_villages_bulk(pool: &PgPool, villages: &[NewVillage<'_>], default_map_size: i32) -> Result<Vec<MapData>> {
+    let coordinates = villages.iter().map(|village| (village.x, village.y));
+    let (server_id, table_name) = get_manual_village_table(pool, coordinates, default_map_size).await?;
+    let mut tx = pool.begin().await?;
+
+    let mut created = Vec::with_capacity(villages.len());
+    for village in villages {
+        created.push(insert_manual_village(&mut tx, &table_name, server_id, village).await?);
+    }
+
+    tx.commit().await?;
+    Ok(created)
+}
+
+// Manually created villages go into the active server's latest snapshot, so they show
+// up alongside the villages already on the map; today's table is only created for a
+// server with no snapshot yet. They are checked against the server's map size first, so
+// a rejected request changes nothing.
+async fn get_manual_village_table(
+    pool: &PgPool,
+    coordinates: impl IntoIterator<Item = (i32, i32)>,
+    default_map_size: i32,
+) -> Result<(i32, String)> {
+    let server = match get_active_server(pool).await? {
+        Some(server) => server,
+        None => return Err(NoActiveServer.into()),
+    };
+
+    let (min, max) = geo::coordinate_range(server.map_size_or(default_map_size));
+    if let Some((x, y)) = coordinates
+        .into_iter()
+        .find(|(x, y)| !(min..=max).contains(x) || !(min..=max).contains(y))
+    {
+        return Err(CoordinateOutOfRange { x, y, min, max }.into());
+    }
+
+    // Creating the table again is a no-op that also brings an old layout up to date
+    let date = match get_latest_data_date_for_server(pool, server.id).await? {
+        Some(date) => date,
+        None => chrono::Utc::now().date_naive(),
+    };
+    let table_name = create_table_for_server_and_date(pool, server.id, date).await?;
+    Ok((server.id, table_name))
+}
+
+async fn insert_manual_village(
+    conn: &mut PgConnection,
+    table_name: &str,
+    server_id: i32,
+    village: &NewVillage<'_>,
+) -> Result<MapData> {
+    let query = format!(
+        "INSERT INTO {} (server_id, village, x, y, population, player, alliance) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id, village, x, y, population, player, alliance, worldid, tid",
+        table_name
+    );
+    let row = sqlx::query(&query)
+        .bind(server_id)
+        .bind(village.name)
+        .bind(village.x)
+        .bind(village.y)
+        .bind(village.population as i32)
+        .bind("Unknown Player")
+        .bind("No Alliance")
+        .fetch_one(conn)
+        .await
+        .map_err(|e| match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => anyhow::Error::new(CoordinateOccupied { x: village.x, y: village.y }),
+            _ => e.into(),
+        })?;
+
+    Ok(MapData::from_row(&row)?)
+}
+
+// A row of an uploaded villages CSV. Empty player and alliance cells are kept as empty
+// strings, as in map.sql dumps; an empty worldid keeps whatever the village already had.
+pub struct ImportedVillage {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub population: i32,
+    pub player: String,
+    pub alliance: String,
+    pub worldid: Option<i32>,
+}
+
+#[derive(Default)]
+pub struct ImportCounts {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+// Upserts into today's snapshot of the active server by coordinates, all or nothing.
+// Rows identical to the stored village are left alone so their updated_at stays put; the
+// export writes NULL and '' alike, so the two count as the same.
+// Coordinates must be unique within `villages`.
+pub async fn import_villages(pool: &PgPool, villages: &[ImportedVillage], default_map_size: i32) -> Result<ImportCounts> {
+    let _timer = QueryTimer::start("import_villages");
+    let coordinates = villages.iter().map(|village| (village.x, village.y));
+    let (server_id, table_name) = get_manual_village_table(pool, coordinates, default_map_size).await?;
+    let mut tx = pool.begin().await?;
+
+    let mut counts = ImportCounts::default();
+    for batch in villages.chunks(INSERT_BATCH_SIZE) {
+        let mut query = QueryBuilder::<Postgres>::new(format!(
+            "INSERT INTO {} AS v (server_id, village, x, y, population, player, alliance, worldid) ",
+            table_name
+        ));
+        query.push_values(batch, |mut row, village| {
+            row.push_bind(server_id)
+                .push_bind(&village.name)
+                .push_bind(village.x)
+                .push_bind(village.y)
+                .push_bind(village.population)
+                .push_bind(&village.player)
+                .push_bind(&village.alliance)
+                .push_bind(village.worldid);
+        });
+        query.push(
+            " ON CONFLICT (server_id, x, y) DO UPDATE SET \
+             village = EXCLUDED.village, population = EXCLUDED.population, player = EXCLUDED.player, \
+             alliance = EXCLUDED.alliance, worldid = COALESCE(EXCLUDED.worldid, v.worldid), updated_at = NOW() \
+             WHERE (v.village, v.population, COALESCE(v.player, ''), COALESCE(v.alliance, ''), v.worldid) \
+             IS DISTINCT FROM (EXCLUDED.village, EXCLUDED.population, EXCLUDED.player, EXCLUDED.alliance, \
+             COALESCE(EXCLUDED.worldid, v.worldid)) \
+             RETURNING (xmax = 0) AS inserted",
+        );
+
+        // Rows skipped by the WHERE return nothing, which is how unchanged ones are counted
+        let rows = query.build().fetch_all(&mut *tx).await?;
+        let inserted = rows.iter().filter(|row| row.get::<bool, _>("inserted")).count();
+        counts.inserted += inserted;
+        counts.updated += rows.len() - inserted;
+        counts.unchanged += batch.len() - rows.len();
+    }
+
+    tx.commit().await?;
+    Ok(counts)
+}
+
+// The active server and its most recent snapshot table, if both exist
+async fn get_active_latest_table(pool: &PgPool) -> Result<Option<(i32, String)>> {
+    let server = match get_active_server(pool).await? {
+        Some(server) => server,
+        None => return Ok(None),
+    };
+
+    Ok(get_latest_table_for_server(pool, server.id)
+        .await?
+        .map(|table_name| (server.id, table_name)))
+}
+
+// Everything a full map response depends on. Reloads and manual edits touch updated_at
+// and deletions change the count, so any change to the snapshot changes the version.
+#[derive(Clone, PartialEq)]
+pub struct SnapshotVersion {
+    pub server_id: i32,
+    pub date: chrono::NaiveDate,
+    pub map_size: i32,
+    pub village_count: i64,
+    pub last_modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub async fn get_active_snapshot_version(pool: &PgPool, default_map_size: i32) -> Result<Option<SnapshotVersion>> {
+    match get_active_server(pool).await? {
+        Some(server) => get_snapshot_version_for_server(pool, &server, default_map_size).await,
+        None => Ok(None),
+    }
+}
+
+pub async fn get_snapshot_version_for_server(
+    pool: &PgPool,
+    server: &Server,
+    default_map_size: i32,
+) -> Result<Option<SnapshotVersion>> {
+    // Only the served snapshot is counted, not every table the server has
+    let Some(date) = get_latest_data_date_for_server(pool, server.id).await? else {
+        return Ok(None);
+    };
+
+    let query = format!(
+        "SELECT COUNT(*) as village_count, MAX(updated_at) as last_modified FROM {} WHERE server_id = $1",
+        get_table_name_for_server_and_date(server.id, date)
+    );
+    let row = sqlx::query(&query).bind(server.id).fetch_one(pool).await?;
+
+    Ok(Some(SnapshotVersion {
+        server_id: server.id,
+        date,
+        map_size: server.map_size_or(default_map_size),
+        village_count: row.get("village_count"),
+        last_modified: row.get("last_modified"),
+    }))
+}
+
+// Only the fields that are set are written; None leaves the column untouched
+#[derive(Default)]
+pub struct VillageUpdate<'a> {
+    pub name: Option<&'a str>,
+    pub population: Option<u32>,
+    pub player: Option<&'a str>,
+    pub alliance: Option<&'a str>,
+}
+
+impl VillageUpdate<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.population.is_none() && self.player.is_none() && self.alliance.is_none()
+    }
+}
+
+pub async fn update_village(pool: &PgPool, id: u32, update: &VillageUpdate<'_>) -> Result<Option<MapData>> {
+    let (server_id, table_name) = match get_active_latest_table(pool).await? {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+
+    let mut query = QueryBuilder::<Postgres>::new(format!("UPDATE {} SET updated_at = NOW()", table_name));
+    if let Some(name) = update.name {
+        query.push(", village = ").push_bind(name);
+    }
+    if let Some(population) = update.population {
+        query.push(", population = ").push_bind(population as i32);
+    }
+    if let Some(player) = update.player {
+        query.push(", player = ").push_bind(player);
+    }
+    if let Some(alliance) = update.alliance {
+        query.push(", alliance = ").push_bind(alliance);
+    }
+    query
+        .push(" WHERE server_id = ")
+        .push_bind(server_id)
+        .push(" AND id = ")
+        .push_bind(id as i32)
+        .push(" RETURNING id, village, x, y, population, player, alliance, worldid, tid");
+
+    let result = query.build().fetch_optional(pool).await?;
+
+    if let Some(row) = result {
+        Ok(Some(MapData::from_row(&row)?))
+    } else {
+        Ok(None)
+    }
+}
+
+pub async fn delete_village(pool: &PgPool, id: u32) -> Result<bool> {
+    let (server_id, table_name) = match get_active_latest_table(pool).await? {
+        Some(target) => target,
+        None => return Ok(false),
+    };
+
+    let query = format!("DELETE FROM {} WHERE server_id = $1 AND id = $2", table_name);
+    let result = sqlx::query(&query)
+        .bind(server_id)
+        .bind(id as i32)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// What happened to the rows of a map.sql load. Unrecognized statements are skipped rather
+// than failed, since dumps may carry other tables alongside x_world.
+#[derive(Serialize, Default, Clone)]
+pub struct LoadStats {
+    pub inserted: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    // Rows dropped because a later row in the same dump had the same coordinates
+    pub duplicates: usize,
+    pub failed_samples: Vec<String>,
+    // Layout of the first parsed row; dumps do not mix layouts in practice
+    pub layout: Option<&'static str>,
+}
+
+#[derive(Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadStage {
+    // Downloading and parsing the dump; `total` is not known yet
+    #[default]
+    Fetching,
+    Inserting,
+}
+
+// Where a load that reports progress has got to. Updated after every inserted batch, so
+// `processed` counts the villages written so far out of the `total` parsed from the dump.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct LoadProgress {
+    pub stage: LoadStage,
+    pub processed: usize,
+    pub total: usize,
+    pub inserted: usize,
+    pub failed: usize,
+}
+
+pub type ProgressSender = tokio::sync::watch::Sender<LoadProgress>;
+
+// Enough to spot a format change without echoing a whole broken dump back
+const MAX_FAILED_SAMPLES: usize = 5;
+const MAX_FAILED_SAMPLE_LENGTH: usize = 200;
+
+impl LoadStats {
+    fn record_failure(&mut self, sample: impl AsRef<str>) {
+        self.failed += 1;
+        if self.failed_samples.len() < MAX_FAILED_SAMPLES {
+            self.failed_samples.push(sample.as_ref().chars().take(MAX_FAILED_SAMPLE_LENGTH).collect());
+        }
+    }
+}
+
+pub async fn execute_sql_for_server(
+    pool: &PgPool,
+    sql_content: &str,
+    server_id: i32,
+    source_url: &str,
+    progress: Option<&ProgressSender>,
+) -> Result<LoadStats> {
+    let _timer = QueryTimer::start("load_snapshot");
+    let today = chrono::Utc::now().date_naive();
+    
+    // Create table for today if it doesn't exist
+    let table_name = create_table_for_server_and_date(pool, server_id, today).await?;
+    
+    let (villages, mut stats) = parse_x_world_dump(sql_content, server_id);
+    
+    // Only the streamed reload passes a sender; it hears about every batch
+    let report = |processed: usize, stats: &LoadStats| {
+        if let Some(progress) = progress {
+            progress.send_replace(LoadProgress {
+                stage: LoadStage::Inserting,
+                processed,
+                total: villages.len(),
+                inserted: stats.inserted,
+                failed: stats.failed,
+            });
+        }
+    };
+    report(0, &stats);
+
+    // Refresh today's snapshot atomically: readers keep seeing the previous rows until
+    // the whole dump has been upserted, and any error rolls everything back. Villages
+    // are inserted in batches rather than one round-trip per row
+    let mut tx = pool.begin().await?;
+    let mut processed = 0;
+    for batch in villages.chunks(INSERT_BATCH_SIZE) {
+        insert_village_batch(&mut tx, batch, &table_name, server_id, &mut stats).await?;
+        processed += batch.len();
+        report(processed, &stats);
+    }
+    let village_count = stats.inserted;
+    
+    // NOW() is fixed for the transaction, so every row upserted above carries it; anything
+    // older was not in this dump and has disappeared since the previous load today
+    let stale_query = format!("DELETE FROM {} WHERE server_id = $1 AND updated_at < NOW()", table_name);
+    sqlx::query(&stale_query).bind(server_id).execute(&mut *tx).await?;
+    
+    carry_over_first_seen(&mut tx, pool, server_id, &table_name, today).await?;
+    
+    // Recorded in the same transaction, so a success row always matches committed data
+    sqlx::query(
+        "INSERT INTO load_history (server_id, date, village_count, source_url, status, layout) VALUES ($1, $2, $3, $4, 'success', $5)"
+    )
+    .bind(server_id)
+    .bind(today)
+    .bind(village_count as i32)
+    .bind(source_url)
+    .bind(stats.layout)
+    .execute(&mut *tx)
+    .await?;
+    
+    tx.commit().await?;
+    tracing::info!(server_id, village_count, failed = stats.failed, skipped = stats.skipped, duplicates = stats.duplicates, layout = stats.layout, table = %table_name, "Loaded snapshot");
+    telemetry::record_villages_loaded(server_id, village_count);
+    
+    // The snapshot is already committed, so a failed detection only keeps the old size
+    if let Err(e) = detect_map_size(pool, server_id).await {
+        tracing::warn!(server_id, error = %e, "Failed to detect map size");
+    }
+    
+    Ok(stats)
+}
+
+// Parses the x_world INSERT statements of a map.sql dump. Some dumps repeat a
+// coordinate (re-settle artifacts); the last row seen for it wins, keeping the
+// position of the first so the insert order still follows the dump.
+fn parse_x_world_dump(sql_content: &str, server_id: i32) -> (Vec<ParsedVillage>, LoadStats) {
+    let mut stats = LoadStats::default();
+    let mut villages: Vec<ParsedVillage> = Vec::new();
+    let mut positions: HashMap<(i32, i32), usize> = HashMap::new();
+    
+    // Split by lines and process each line
+    for line in sql_content.lines() {
+        let trimmed = line.trim();
+        
+        // Skip empty lines and comments
+        if trimmed.is_empty() || trimmed.starts_with("--") || trimmed.starts_with("/*") {
+            continue;
+        }
+        
+        // Look for INSERT statements for x_world table
+        if trimmed.to_lowercase().contains("insert into") && 
+           (trimmed.to_lowercase().contains("x_world") || trimmed.to_lowercase().contains("`x_world`")) {
+            
+            // Parse Travian x_world format: INSERT INTO `x_world` VALUES (22028,173,146,5,31912,'Natars 173|146′,1,'Natars',0,",498,NULL,FALSE,NULL,NULL,NULL);
+            // Some dumps put several tuples in one statement: VALUES (...),(...),(...);
+            // The keyword may be in any case; ASCII uppercasing keeps the byte offsets
+            if let Some(values_start) = trimmed.to_ascii_uppercase().find("VALUES") {
+                let values_part = &trimmed[values_start + 6..];
+                
+                for values_str in split_values_tuples(values_part) {
+                    // Parse the comma-separated values; a malformed tuple only skips itself
+                    match parse_x_world_values(values_str) {
+                        Ok((parsed_village, layout)) => {
+                            if stats.layout.is_none() {
+                                tracing::info!(server_id, layout = layout.name, fields = layout.field_count, "Detected x_world layout");
+                                stats.layout = Some(layout.name);
+                            }
+                            match positions.entry((parsed_village.x, parsed_village.y)) {
+                                Entry::Occupied(entry) => {
+                                    villages[*entry.get()] = parsed_village;
+                                    stats.duplicates += 1;
+                                },
+                                Entry::Vacant(entry) => {
+                                    entry.insert(villages.len());
+                                    villages.push(parsed_village);
+                                }
+                            }
+                        },
+                        Err(e) => {
+                            tracing::warn!(server_id, values = %values_str, error = %e, "Failed to parse x_world values");
+                            stats.record_failure(format!("{}: {}", e, values_str));
+                        }
+                    }
+                }
+            } else {
+                stats.record_failure(trimmed);
+            }
+        } else {
+            stats.skipped += 1;
+        }
+    }
+    
+    if stats.duplicates > 0 {
+        tracing::warn!(server_id, duplicates = stats.duplicates, "Collapsed duplicate coordinates in map.sql");
+    }
+    
+    (villages, stats)
+}
+
+// Villages that already existed in the previous snapshot keep their original first_seen
+async fn carry_over_first_seen(
+    conn: &mut PgConnection,
+    pool: &PgPool,
+    server_id: i32,
+    table_name: &str,
+    today: chrono::NaiveDate,
+) -> Result<()> {
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    let previous_date = match available_dates.iter().map(|(date, _)| *date).find(|date| *date < today) {
+        Some(date) => date,
+        None => return Ok(()),
+    };
+    let previous_table = get_table_name_for_server_and_date(server_id, previous_date);
+
+    // Snapshots from before first_seen existed fall back to their own date
+    let add_column = format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS first_seen DATE", previous_table);
+    sqlx::query(&add_column).execute(&mut *conn).await?;
+
+    let query = format!(
+        r#"
+        UPDATE {} t
+        SET first_seen = COALESCE(p.first_seen, $2)
+        FROM {} p
+        WHERE t.server_id = $1 AND p.server_id = $1
+          AND t.x = p.x AND t.y = p.y
+          AND t.vid IS NOT DISTINCT FROM p.vid
+          AND COALESCE(p.first_seen, $2) < t.first_seen
+        "#,
+        table_name, previous_table
+    );
+    sqlx::query(&query)
+        .bind(server_id)
+        .bind(previous_date)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+// Splits the VALUES section of an INSERT statement into the contents of each
+// top-level parenthesized tuple, ignoring parentheses and commas inside quotes.
+// An unterminated trailing tuple is dropped.
+fn split_values_tuples(values_part: &str) -> Vec<&str> {
+    let mut tuples = Vec::new();
+    let mut depth = 0;
+    let mut tuple_start = 0;
+    let mut in_quotes = false;
+    let mut quote_char = '"';
+    let mut chars = values_part.char_indices().peekable();
+    
+    while let Some((index, ch)) = chars.next() {
+        if in_quotes {
+            if ch == '\\' {
+                // Backslash escape: the next character is part of the string
+                chars.next();
+            } else if ch == quote_char {
+                if chars.peek().map(|(_, next)| *next) == Some(quote_char) {
+                    // Doubled quote ('') is an escaped quote, not the end of the string
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            }
+            continue;
+        }
+        
+        match ch {
+            '"' | '\'' => {
+                in_quotes = true;
+                quote_char = ch;
+            },
+            '(' => {
+                if depth == 0 {
+                    tuple_start = index + 1;
+                }
+                depth += 1;
+            },
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    tuples.push(&values_part[tuple_start..index]);
+                }
+            },
+            _ => {}
+        }
+    }
+    
+    tuples
+}
+
+// A single value from an x_world tuple. Quoted strings are unescaped and have
+// their surrounding quotes removed; bare values (numbers, NULL) are kept as-is.
+struct SqlField {
+    value: String,
+    quoted: bool,
+}
+
+impl SqlField {
+    fn as_i32(&self) -> Option<i32> {
+        self.value.parse::<i32>().ok()
+    }
+    
+    // NULL and empty bare values become None; quoted strings are kept even when empty
+    fn as_text(&self) -> Option<String> {
+        if !self.quoted && (self.value.is_empty() || self.value.eq_ignore_ascii_case("NULL")) {
+            None
+        } else {
+            Some(clean_name(&self.value))
+        }
+    }
+    
+    // Accepts TRUE/FALSE as well as 1/0; anything else (including NULL) is false
+    fn as_bool(&self) -> bool {
+        self.value.eq_ignore_ascii_case("TRUE") || self.value == "1"
+    }
+}
+
+// Badly re-encoded dumps leave a prime (U+2032) where a closing quote was, either
+// as the character itself or as its UTF-8 bytes misread as Latin-1 ("â€²")
+fn clean_name(name: &str) -> String {
+    const PRIME_ARTIFACTS: [&str; 2] = ["\u{2032}", "\u{e2}\u{20ac}\u{b2}"];
+
+    let mut cleaned = name;
+    loop {
+        let before = cleaned;
+        for artifact in PRIME_ARTIFACTS {
+            cleaned = cleaned.trim_start_matches(artifact).trim_end_matches(artifact);
+        }
+        if cleaned == before {
+            break;
+        }
+    }
+    cleaned.to_string()
+}
+
+// Splits a tuple's contents on top-level commas. Inside a quoted field a
+// backslash escapes the next character ('L\'Alliance') and a doubled quote
+// stands for a literal one ('Team ''A'''), so neither ends the field.
+fn split_x_world_fields(values_str: &str) -> Vec<SqlField> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut quote_char = '"';
+    let mut chars = values_str.chars().peekable();
+    
+    while let Some(ch) = chars.next() {
+        if in_quotes {
+            if ch == '\\' {
+                match chars.next() {
+                    Some('n') => current.push('\n'),
+                    Some('r') => current.push('\r'),
+                    Some('t') => current.push('\t'),
+                    Some(escaped) => current.push(escaped),
+                    None => {}
+                }
+            } else if ch == quote_char {
+                if chars.peek() == Some(&quote_char) {
+                    chars.next();
+                    current.push(quote_char);
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                current.push(ch);
+            }
+            continue;
+        }
+        
+        match ch {
+            '"' | '\'' => {
+                in_quotes = true;
+                quoted = true;
+                quote_char = ch;
+            },
+            ',' => {
+                let value = if quoted { std::mem::take(&mut current) } else { current.trim().to_string() };
+                fields.push(SqlField { value, quoted });
+                current.clear();
+                quoted = false;
+            },
+            // Whitespace between a closing quote and the next comma is not part of the value
+            _ if quoted => {},
+            _ => current.push(ch),
+        }
+    }
+    if quoted || !current.trim().is_empty() {
+        let value = if quoted { current } else { current.trim().to_string() };
+        fields.push(SqlField { value, quoted });
+    }
+    
+    fields
+}
+
+// Column naming of the dated tables, in the order of the "extended" x_world layout.
+// map.sql export writes rows in this order, so exported dumps re-import as "extended".
+pub const X_WORLD_COLUMNS: [&str; 14] = [
+    "worldid", "x", "y", "tid", "vid", "village", "uid", "player", "aid", "alliance",
+    "population", "capital", "isWW", "wwname",
+];
+
+// Every layout starts with worldid through population in the same order
+const X_WORLD_REQUIRED_FIELDS: usize = 11;
+
+// Where the remaining fields sit in one known x_world layout. Layouts are told apart by
+// their number of fields; columns a layout does not have are left NULL/false.
+struct XWorldLayout {
+    name: &'static str,
+    field_count: usize,
+    population: usize,
+    capital: Option<usize>,
+    is_ww: Option<usize>,
+    ww_name: Option<usize>,
+}
+
+const X_WORLD_LAYOUTS: [XWorldLayout; 3] = [
+    // Older dumps that stop after population
+    XWorldLayout { name: "classic", field_count: 11, population: 10, capital: None, is_ww: None, ww_name: None },
+    // population, capital, isWW, wwname; also what map.sql export writes
+    XWorldLayout { name: "extended", field_count: 14, population: 10, capital: Some(11), is_ww: Some(12), ww_name: Some(13) },
+    // Travian Legends: population, region, isCapital, isCity, hasHarbor, victoryPoints
+    XWorldLayout { name: "legends", field_count: 16, population: 10, capital: Some(12), is_ww: None, ww_name: None },
+];
+
+// A row with an unrecognized number of fields is read with the classic layout, whose
+// fields every layout shares, so unknown trailing columns can never shift population
+fn detect_x_world_layout(field_count: usize) -> &'static XWorldLayout {
+    X_WORLD_LAYOUTS
+        .iter()
+        .find(|layout| layout.field_count == field_count)
+        .unwrap_or(&X_WORLD_LAYOUTS[0])
+}
+
+struct ParsedVillage {
+    worldid: Option<i32>,
+    x: i32,
+    y: i32,
+    tid: Option<i32>,
+    vid: Option<i32>,
+    village: String,
+    uid: Option<i32>,
+    player: Option<String>,
+    aid: Option<i32>,
+    alliance: Option<String>,
+    population: i32,
+    capital: Option<String>,
+    is_ww: bool,
+    ww_name: Option<String>,
+}
+
+fn parse_x_world_values(values_str: &str) -> Result<(ParsedVillage, &'static XWorldLayout)> {
+    // Split by comma, but be careful with quoted strings
+    let parts = split_x_world_fields(values_str);
+    
+    // Ensure we have at least the minimum required fields
+    if parts.len() < X_WORLD_REQUIRED_FIELDS {
+        return Err(anyhow::anyhow!("Not enough values in x_world record"));
+    }
+    
+    let layout = detect_x_world_layout(parts.len());
+    let field = |index: Option<usize>| index.and_then(|index| parts.get(index));
+    
+    // Parse the values according to the detected layout
+    let worldid = parts[0].as_i32();
+    let x = parts[1].as_i32().unwrap_or(0);
+    let y = parts[2].as_i32().unwrap_or(0);
+    let tid = parts[3].as_i32();
+    let vid = parts[4].as_i32();
+    
+    let village = clean_name(&parts[5].value);
+    
+    let uid = parts[6].as_i32();
+    
+    let player = parts[7].as_text();
+    
+    let aid = parts[8].as_i32();
+    
+    let alliance = parts[9].as_text();
+    
+    let population = parts[layout.population].as_i32().unwrap_or(0);
+    
+    let capital = field(layout.capital).and_then(SqlField::as_text);
+    let is_ww = field(layout.is_ww).is_some_and(SqlField::as_bool);
+    let ww_name = field(layout.ww_name).and_then(SqlField::as_text);
+    
+    let parsed = ParsedVillage {
+        worldid,
+        x,
+        y,
+        tid,
+        vid,
+        village,
+        uid,
+        player,
+        aid,
+        alliance,
+        population,
+        capital,
+        is_ww,
+        ww_name,
+    };
+    
+    Ok((parsed, layout))
+}
+
+// Rows per multi-VALUES INSERT; 1000 rows x 15 columns stays well under
+// Postgres' 65535 bind-parameter limit
+const INSERT_BATCH_SIZE: usize = 1000;
+
+// Inserts a batch in a single statement inside its own savepoint. If the batch
+// fails (e.g. one over-long name), it is retried row by row so only the bad
+// rows are lost. Returns the number of villages inserted.
+async fn insert_village_batch(
+    conn: &mut PgConnection,
+    villages: &[ParsedVillage],
+    table_name: &str,
+    server_id: i32,
+    stats: &mut LoadStats,
+) -> Result<()> {
+    if villages.is_empty() {
+        return Ok(());
+    }
+    
+    // A failed statement aborts a Postgres transaction, so each attempt runs in a savepoint
+    let mut savepoint = conn.begin().await?;
+    match insert_parsed_villages_to_table_with_server(&mut savepoint, villages, table_name, server_id).await {
+        Ok(_) => {
+            savepoint.commit().await?;
+            stats.inserted += villages.len();
+            return Ok(());
+        },
+        Err(e) => {
+            savepoint.rollback().await?;
+            tracing::warn!(server_id, batch_size = villages.len(), error = %e, "Batch insert failed, retrying one by one");
+        }
+    }
+    
+    for village in villages {
+        let mut savepoint = conn.begin().await?;
+        match insert_parsed_villages_to_table_with_server(&mut savepoint, std::slice::from_ref(village), table_name, server_id).await {
+            Ok(_) => {
+                savepoint.commit().await?;
+                stats.inserted += 1;
+            },
+            Err(e) => {
+                savepoint.rollback().await?;
+                tracing::warn!(server_id, x = village.x, y = village.y, error = %e, "Failed to insert village");
+                stats.record_failure(format!("Insert of ({}|{}) failed: {}", village.x, village.y, e));
+                // Continue with other villages
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+async fn insert_parsed_villages_to_table_with_server(conn: &mut PgConnection, villages: &[ParsedVillage], table_name: &str, server_id: i32) -> Result<()> {
+    let mut query = QueryBuilder::<Postgres>::new(format!(
+        "INSERT INTO {} (server_id, {}) ",
+        table_name,
+        X_WORLD_COLUMNS.join(", ")
+    ));
+    
+    query.push_values(villages, |mut row, village| {
+        row.push_bind(server_id)
+            .push_bind(village.worldid)
+            .push_bind(village.x)
+            .push_bind(village.y)
+            .push_bind(village.tid)
+            .push_bind(village.vid)
+            .push_bind(&village.village)
+            .push_bind(village.uid)
+            .push_bind(&village.player)
+            .push_bind(village.aid)
+            .push_bind(&village.alliance)
+            .push_bind(village.population)
+            .push_bind(&village.capital)
+            .push_bind(village.is_ww)
+            .push_bind(&village.ww_name);
+    });
+    
+    // Reloading the same day updates villages in place, keeping created_at and first_seen
+    query.push(
+        " ON CONFLICT (server_id, x, y) DO UPDATE SET \
+         worldid = EXCLUDED.worldid, tid = EXCLUDED.tid, vid = EXCLUDED.vid, village = EXCLUDED.village, \
+         uid = EXCLUDED.uid, player = EXCLUDED.player, aid = EXCLUDED.aid, alliance = EXCLUDED.alliance, \
+         population = EXCLUDED.population, capital = EXCLUDED.capital, isWW = EXCLUDED.isWW, \
+         wwname = EXCLUDED.wwname, updated_at = NOW()",
+    );
+    
+    query.build().execute(conn).await?;
+    
+    Ok(())
+}
+
+// Server management functions
+pub async fn get_all_servers(pool: &PgPool) -> Result<Vec<Server>> {
+    let rows = sqlx::query(&format!("SELECT {} FROM servers ORDER BY name", SERVER_COLUMNS))
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(server_from_row).collect())
+}
+
+#[derive(Debug)]
+pub struct ServerNameTaken {
+    pub name: String,
+}
+
+impl std::fmt::Display for ServerNameTaken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "A server named '{}' already exists", self.name)
+    }
+}
+
+impl std::error::Error for ServerNameTaken {}
+
+pub async fn add_server(pool: &PgPool, name: &str, url: &str) -> Result<Server> {
+    let row = sqlx::query(
+        &format!("INSERT INTO servers (name, url, is_active) VALUES ($1, $2, $3) RETURNING {}", SERVER_COLUMNS)
+    )
+    .bind(name)
+    .bind(url)
+    .bind(false) // New servers are not active by default
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db_error) if db_error.is_unique_violation() => anyhow::Error::new(ServerNameTaken { name: name.to_string() }),
+        _ => e.into(),
+    })?;
+
+    let mut server = server_from_row(&row);
+
+    // If this is the first server, make it active; the caller starts its first load
+    let all_servers = get_all_servers(pool).await?;
+    if all_servers.len() == 1 {
+        set_active_server(pool, server.id).await?;
+        server.is_active = true;
+    }
+
+    Ok(server)
+}
+
+pub async fn set_active_server(pool: &PgPool, server_id: i32) -> Result<()> {
+    // First, set all servers to inactive
+    sqlx::query("UPDATE servers SET is_active = FALSE")
+        .execute(pool)
+        .await?;
+    
+    // Then set the specified server as active
+    sqlx::query("UPDATE servers SET is_active = TRUE WHERE id = $1")
+        .bind(server_id)
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn remove_server(pool: &PgPool, server_id: i32) -> Result<()> {
+    // First, check if this server is currently active
+    let active_server = get_active_server(pool).await?;
+    let is_removing_active = active_server.is_some_and(|server| server.id == server_id);
+    
+    // Get all available dates for this server to clean up data tables
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    
+    // Drop all data tables for this server
+    for (date, _) in available_dates {
+        let table_name = get_table_name_for_server_and_date(server_id, date);
+        let drop_query = format!("DROP TABLE IF EXISTS {}", table_name);
+        sqlx::query(&drop_query).execute(pool).await?;
+        tracing::info!(server_id, table = %table_name, "Dropped snapshot table");
+    }
+    
+    // Remove the server from the servers table
+    sqlx::query("DELETE FROM servers WHERE id = $1")
+        .bind(server_id)
+        .execute(pool)
+        .await?;
+    
+    // If we removed the active server, set another server as active (if any exist)
+    if is_removing_active {
+        let remaining_servers = get_all_servers(pool).await?;
+        if let Some(first_server) = remaining_servers.first() {
+            set_active_server(pool, first_server.id).await?;
+            tracing::info!(server_id = first_server.id, server = %first_server.name, "Set server as active after removing the active server");
+        }
+    }
+    
+    Ok(())
+}
+
+// Only looks at table names, unlike get_available_dates_for_server which counts every
+// snapshot's villages too
+pub async fn get_latest_data_date_for_server(pool: &PgPool, server_id: i32) -> Result<Option<chrono::NaiveDate>> {
+    let latest_table: Option<String> = sqlx::query_scalar(
+        r#"
+        SELECT table_name
+        FROM information_schema.tables
+        WHERE table_schema = 'public'
+        AND table_name ~ $1
+        ORDER BY table_name DESC
+        LIMIT 1
+        "#
+    )
+    .bind(format!("^villages_server_{}_[0-9]{{4}}_[0-9]{{2}}_[0-9]{{2}}$", server_id))
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(latest_table.and_then(|table_name| {
+        let date_part = table_name.strip_prefix(&format!("villages_server_{}_", server_id))?;
+        chrono::NaiveDate::parse_from_str(date_part, "%Y_%m_%d").ok()
+    }))
+}
+
+pub async fn ping(pool: &PgPool) -> Result<()> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+// Today's table existing is not enough: startup and manually created villages create it
+// too. Only a successful load recorded for today means today's dump is in.
+pub async fn is_new_data_needed_for_server(pool: &PgPool, server_id: i32) -> Result<bool> {
+    let today = chrono::Utc::now().date_naive();
+    
+    let loaded_today: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM load_history WHERE server_id = $1 AND date = $2 AND status = 'success')"
+    )
+    .bind(server_id)
+    .bind(today)
+    .fetch_one(pool)
+    .await?;
+    
+    Ok(!loaded_today)
+}
+
+// Travian servers may serve map.sql.gz directly or gzip the transfer; reqwest
+// does not decompress on its own, so check the URL and headers
+fn is_gzip_response(response: &reqwest::Response, url: &str) -> bool {
+    let header_mentions_gzip = |name: reqwest::header::HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.to_ascii_lowercase().contains("gzip"))
+    };
+
+    url.ends_with(".gz")
+        || header_mentions_gzip(reqwest::header::CONTENT_ENCODING)
+        || header_mentions_gzip(reqwest::header::CONTENT_TYPE)
+}
+
+fn decompress_gzip(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = flate2::read::GzDecoder::new(bytes);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)
+        .map_err(|e| anyhow::anyhow!("Failed to decompress gzipped SQL: {}", e))?;
+    Ok(decompressed)
+}
+
+fn decode_sql_bytes(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(sql_content) => sql_content.to_string(),
+        Err(e) => {
+            tracing::warn!(error = %e, "SQL dump is not valid UTF-8, replacing invalid sequences");
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+    }
+}
+
+// A human-readable summary plus, when a dump was actually loaded, its parse statistics
+#[derive(Serialize)]
+pub struct LoadOutcome {
+    pub message: String,
+    pub stats: Option<LoadStats>,
+}
+
+impl LoadOutcome {
+    fn message_only(message: impl Into<String>) -> Self {
+        LoadOutcome { message: message.into(), stats: None }
+    }
+}
+
+// What every load takes from the startup config, however it was started
+#[derive(Clone)]
+pub struct LoadSettings {
+    // Loads that bring in a dump are announced here
+    pub webhook: Option<Arc<Webhook>>,
+    // Snapshots kept per server after a load, unless the server sets retention_days
+    pub table_retention_days: usize,
+}
+
+pub async fn auto_load_data_for_server(
+    pool: &PgPool,
+    locks: &ServerLoadLocks,
+    fetcher: &MapFetcher,
+    settings: &LoadSettings,
+    server: &Server,
+) -> Result<LoadOutcome> {
+    let _guard = locks.try_lock(server.id)?;
+    load_data_for_server(pool, fetcher, settings, server, None).await
+}
+
+// Callers must hold the server's load lock
+pub async fn load_data_for_server(
+    pool: &PgPool,
+    fetcher: &MapFetcher,
+    settings: &LoadSettings,
+    server: &Server,
+    progress: Option<&ProgressSender>,
+) -> Result<LoadOutcome> {
+    // Check if new data is needed
+    if !is_new_data_needed_for_server(pool, server.id).await? {
+        return Ok(LoadOutcome::message_only("Data is up to date"));
+    }
+
+    reload_data_for_server(pool, fetcher, settings, server, progress).await
+}
+
+// Fetches and loads the dump even when today's snapshot already exists, refreshing it in
+// place. Callers must hold the server's load lock. Every load that brings in a dump is
+// announced to the settings' webhook, whether the scheduler, an activation or a reload
+// started it.
+pub async fn reload_data_for_server(
+    pool: &PgPool,
+    fetcher: &MapFetcher,
+    settings: &LoadSettings,
+    server: &Server,
+    progress: Option<&ProgressSender>,
+) -> Result<LoadOutcome> {
+    let candidates = map_sql_candidates(&server.url);
+    tracing::info!(server_id = server.id, server = %server.name, url = %server.url, "Auto-loading data");
+
+    match fetch_and_load(pool, fetcher, settings, server, &candidates, progress).await {
+        Ok(stats) => {
+            telemetry::record_auto_load_success(server.id);
+            // Best effort: the snapshot is loaded either way
+            if let Err(e) = record_watchlist_changes(pool, server.id).await {
+                tracing::warn!(server_id = server.id, error = %e, "Could not record watchlist changes");
+            }
+            if let Some(webhook) = &settings.webhook {
+                match LoadSummary::collect(pool, server, &stats).await {
+                    Ok(summary) => webhook.notify(summary),
+                    Err(e) => tracing::warn!(server_id = server.id, error = %e, "Could not build webhook notification"),
+                }
+            }
+            let mut message = format!("Successfully loaded {} villages for server '{}'", stats.inserted, server.name);
+            if stats.failed > 0 {
+                message.push_str(&format!(" ({} rows failed to parse or insert)", stats.failed));
+            }
+            Ok(LoadOutcome { message, stats: Some(stats) })
+        },
+        Err(e) => {
+            record_failed_load(pool, server.id, &server.url, &e).await;
+            Err(e)
+        }
+    }
+}
+
+// Where Travian servers publish their dump, relative to the server's address
+const MAP_SQL_CANDIDATE_PATHS: &[&str] = &["map.sql", "map.sql.gz", "game/map.sql"];
+
+// A stored URL that already points at the dump is used as is, apart from falling back
+// from map.sql to its gzipped twin; otherwise the usual locations are probed in order
+fn map_sql_candidates(server_url: &str) -> Vec<String> {
+    if server_url.ends_with("map.sql.gz") {
+        return vec![server_url.to_string()];
+    }
+    if server_url.ends_with("map.sql") {
+        return vec![server_url.to_string(), format!("{}.gz", server_url)];
+    }
+
+    let base = server_url.trim_end_matches('/');
+    MAP_SQL_CANDIDATE_PATHS
+        .iter()
+        .map(|path| format!("{}/{}", base, path))
+        .collect()
+}
+
+// Moves on to the next candidate on any unsuccessful status. Timeouts and connection
+// errors abort straight away, since every candidate lives on the same host.
+async fn fetch_first_available(fetcher: &MapFetcher, candidates: &[String]) -> Result<(String, reqwest::Response)> {
+    let mut failures = Vec::new();
+
+    for url in candidates {
+        let response = fetcher.get(url).await?;
+        if response.status().is_success() {
+            tracing::info!(url = %url, "Found SQL dump");
+            return Ok((url.clone(), response));
+        }
+
+        tracing::info!(url = %url, status = %response.status(), "SQL dump not available");
+        failures.push(format!("HTTP error {}: Failed to fetch SQL from {}", response.status(), url));
+    }
+
+    Err(anyhow::anyhow!("{}", failures.join("; ")))
+}
+
+#[derive(Serialize)]
+pub struct ProbeAttempt {
+    pub url: String,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+// Where a server's dump was found, if anywhere, and what each candidate answered
+#[derive(Serialize)]
+pub struct UrlProbe {
+    pub found: Option<String>,
+    pub attempts: Vec<ProbeAttempt>,
+}
+
+// Checks the same candidates a load would try, in the same order, without downloading
+// anything. Like fetch_first_available it stops at the first success and gives up on
+// the rest after a timeout or connection error.
+pub async fn probe_server_url(fetcher: &MapFetcher, server_url: &str) -> UrlProbe {
+    let mut attempts = Vec::new();
+
+    for url in map_sql_candidates(server_url) {
+        match fetcher.probe(&url).await {
+            Ok(status) if status.is_success() => {
+                attempts.push(ProbeAttempt { url: url.clone(), status: Some(status.as_u16()), error: None });
+                return UrlProbe { found: Some(url), attempts };
+            },
+            Ok(status) => attempts.push(ProbeAttempt { url, status: Some(status.as_u16()), error: None }),
+            Err(e) => {
+                attempts.push(ProbeAttempt { url, status: None, error: Some(e.to_string()) });
+                break;
+            },
+        }
+    }
+
+    UrlProbe { found: None, attempts }
+}
+
+async fn fetch_and_load(
+    pool: &PgPool,
+    fetcher: &MapFetcher,
+    settings: &LoadSettings,
+    server: &Server,
+    candidates: &[String],
+    progress: Option<&ProgressSender>,
+) -> Result<LoadStats> {
+    let (fetched_url, response) = fetch_first_available(fetcher, candidates).await?;
+
+    // Read raw bytes rather than response.text(), which would trust a (often wrong)
+    // charset header; Travian dumps are UTF-8
+    let is_gzip = is_gzip_response(&response, &fetched_url);
+    let bytes = fetcher.read_body(response).await?;
+    let sql_content = if is_gzip {
+        decode_sql_bytes(&decompress_gzip(&bytes)?)
+    } else {
+        decode_sql_bytes(&bytes)
+    };
+
+    // Execute the SQL for this specific server
+    let stats = execute_sql_for_server(pool, &sql_content, server.id, &fetched_url, progress).await?;
+
+    // Cleanup old tables (keep only the most recent per server)
+    cleanup_old_tables(pool, settings.table_retention_days).await?;
+
+    Ok(stats)
+}
+
+// Best effort: a failure to record the failure is only logged, so the load error itself
+// still reaches the caller
+async fn record_failed_load(pool: &PgPool, server_id: i32, source_url: &str, error: &anyhow::Error) {
+    let result = sqlx::query(
+        "INSERT INTO load_history (server_id, date, source_url, status, error) VALUES ($1, $2, $3, 'failed', $4)"
+    )
+    .bind(server_id)
+    .bind(chrono::Utc::now().date_naive())
+    .bind(source_url)
+    .bind(format!("{:#}", error))
+    .execute(pool)
+    .await;
+
+    if let Err(e) = result {
+        tracing::warn!(server_id, error = %e, "Could not record failed load");
+    }
+}
+
+#[derive(Serialize)]
+pub struct DataFreshness {
+    pub server_id: i32,
+    // From the most recent successful load
+    pub last_loaded_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub village_count: Option<i32>,
+    pub snapshot_date: Option<chrono::NaiveDate>,
+    pub source_url: Option<String>,
+    pub layout: Option<String>,
+    // From the most recent attempt, which may be a failure after that success
+    pub last_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_status: Option<String>,
+    pub last_error: Option<String>,
+}
+
+pub async fn get_data_freshness(pool: &PgPool, server_id: i32) -> Result<DataFreshness> {
+    let last_success = sqlx::query(
+        "SELECT loaded_at, village_count, date, source_url, layout FROM load_history
+         WHERE server_id = $1 AND status = 'success'
+         ORDER BY loaded_at DESC LIMIT 1"
+    )
+    .bind(server_id)
+    .fetch_optional(pool)
+    .await?;
+
+    let last_attempt = sqlx::query(
+        "SELECT loaded_at, status, error FROM load_history
+         WHERE server_id = $1
+         ORDER BY loaded_at DESC LIMIT 1"
+    )
+    .bind(server_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(DataFreshness {
+        server_id,
+        last_loaded_at: last_success.as_ref().map(|row| row.get("loaded_at")),
+        village_count: last_success.as_ref().map(|row| row.get("village_count")),
+        snapshot_date: last_success.as_ref().map(|row| row.get("date")),
+        source_url: last_success.as_ref().map(|row| row.get("source_url")),
+        layout: last_success.as_ref().and_then(|row| row.get("layout")),
+        last_attempt_at: last_attempt.as_ref().map(|row| row.get("loaded_at")),
+        last_status: last_attempt.as_ref().map(|row| row.get("status")),
+        last_error: last_attempt.as_ref().and_then(|row| row.get("error")),
+    })
+}
+
+#[derive(Serialize)]
+pub struct SnapshotInfo {
+    pub date: chrono::NaiveDate,
+    pub village_count: i32,
+    // From load_history; None for snapshots loaded before it was recorded
+    pub loaded_at: Option<chrono::DateTime<chrono::Utc>>,
+    // Status of the latest load attempt for the date, which may be a failed reload
+    // that left the earlier data in place
+    pub status: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SnapshotCoverage {
+    pub server_id: i32,
+    // Newest first
+    pub snapshots: Vec<SnapshotInfo>,
+    // Days between the oldest and newest snapshot with no data, oldest first. Diffs,
+    // AFK and inactivity searches across a gap compare snapshots further apart than
+    // their day counts suggest.
+    pub missing_dates: Vec<chrono::NaiveDate>,
+}
+
+pub async fn get_snapshot_coverage(pool: &PgPool, server_id: i32) -> Result<SnapshotCoverage> {
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let rows = sqlx::query(
+        "SELECT date,
+                MAX(loaded_at) FILTER (WHERE status = 'success') AS loaded_at,
+                (ARRAY_AGG(status ORDER BY loaded_at DESC))[1] AS status
+         FROM load_history
+         WHERE server_id = $1
+         GROUP BY date"
+    )
+    .bind(server_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut loads: HashMap<chrono::NaiveDate, (Option<chrono::DateTime<chrono::Utc>>, Option<String>)> = rows
+        .iter()
+        .map(|row| (row.get("date"), (row.get("loaded_at"), row.get("status"))))
+        .collect();
+
+    let missing_dates = match (available_dates.last(), available_dates.first()) {
+        (Some((oldest, _)), Some((newest, _))) => oldest
+            .iter_days()
+            .take_while(|date| date < newest)
+            .filter(|date| !available_dates.iter().any(|(available, _)| available == date))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let snapshots = available_dates
+        .into_iter()
+        .map(|(date, village_count)| {
+            let (loaded_at, status) = loads.remove(&date).unwrap_or_default();
+            SnapshotInfo { date, village_count, loaded_at, status }
+        })
+        .collect();
+
+    Ok(SnapshotCoverage { server_id, snapshots, missing_dates })
+}
+
+#[derive(Debug)]
+pub struct NoActiveServer;
+
+impl std::fmt::Display for NoActiveServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No active server found")
+    }
+}
+
+impl std::error::Error for NoActiveServer {}
+
+pub async fn get_server(pool: &PgPool, server_id: i32) -> Result<Option<Server>> {
+    let row = sqlx::query(&format!("SELECT {} FROM servers WHERE id = $1", SERVER_COLUMNS))
+        .bind(server_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(server_from_row))
+}
+
+pub async fn get_active_server(pool: &PgPool) -> Result<Option<Server>> {
+    let row = sqlx::query(&format!("SELECT {} FROM servers WHERE is_active = TRUE LIMIT 1", SERVER_COLUMNS))
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(server_from_row))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TribeStats {
+    pub tribe_id: i32,
+    pub tribe_name: String,
+    pub village_count: i32,
+    pub total_population: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlayerStats {
+    pub player_name: String,
+    pub village_count: i32,
+    pub total_population: i64,
+    pub alliance: Option<String>,
+    pub profile_link: Option<String>,
+    pub alliance_link: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+pub enum PlayerSort {
+    Population,
+    VillageCount,
+}
+
+impl PlayerSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "population" => Some(PlayerSort::Population),
+            "village_count" => Some(PlayerSort::VillageCount),
+            _ => None,
+        }
+    }
+
+    // The player name tie-breaker keeps page boundaries stable between requests
+    fn order_by_clause(self) -> &'static str {
+        match self {
+            PlayerSort::Population => " ORDER BY total_population DESC, village_count DESC, player",
+            PlayerSort::VillageCount => " ORDER BY village_count DESC, total_population DESC, player",
+        }
+    }
+}
+
+pub struct PlayerPage {
+    pub players: Vec<PlayerStats>,
+    pub total: i64,
+}
+
+#[derive(Serialize)]
+pub struct PlayerDetail {
+    pub player_name: String,
+    pub alliance: Option<String>,
+    pub total_population: i64,
+    pub village_count: i32,
+    pub villages: Vec<MapData>,
+    pub profile_link: Option<String>,
+    pub alliance_link: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WorldInfo {
+    pub tribe_stats: Vec<TribeStats>,
+    pub top_players: Vec<PlayerStats>,
+    pub total_villages: i32,
+    pub total_population: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AfkVillage {
+    pub village_name: String,
+    pub x: i32,
+    pub y: i32,
+    pub population: i32,
+    pub player_name: String,
+    pub alliance: Option<String>,
+    pub days_without_growth: i32,
+}
+
+#[derive(Serialize)]
+pub struct InactivePlayer {
+    pub player_name: String,
+    pub alliance: Option<String>,
+    pub total_population: i64,
+    pub population_change: i64, // Latest minus the first snapshot of the window, never positive
+    pub village_count: i32,
+    pub villages: Vec<MapData>,
+    pub profile_link: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AllianceStats {
+    pub alliance_name: String,
+    pub alliance_id: Option<i32>,
+    pub member_count: i32,
+    pub village_count: i32,
+    pub total_population: i64,
+    pub average_population_per_village: i32,
+    // Left out by listings that skip the comparison with the previous snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub population_growth: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub growth_percentage: Option<f64>,
+    pub alliance_link: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+pub enum AllianceSort {
+    Population,
+    VillageCount,
+    MemberCount,
+}
+
+impl AllianceSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "population" => Some(AllianceSort::Population),
+            "village_count" => Some(AllianceSort::VillageCount),
+            "member_count" => Some(AllianceSort::MemberCount),
+            _ => None,
+        }
+    }
+
+    // The alliance tag tie-breaker keeps page boundaries stable between requests
+    fn order_by_clause(self) -> &'static str {
+        match self {
+            AllianceSort::Population => " ORDER BY total_population DESC, alliance, aid",
+            AllianceSort::VillageCount => " ORDER BY village_count DESC, total_population DESC, alliance, aid",
+            AllianceSort::MemberCount => " ORDER BY member_count DESC, total_population DESC, alliance, aid",
+        }
+    }
+}
+
+pub struct AlliancePage {
+    pub alliances: Vec<AllianceStats>,
+    pub total: i64,
+}
+
+#[derive(Serialize)]
+pub struct AllianceMember {
+    pub player_name: String,
+    pub village_count: i32,
+    pub total_population: i64,
+    pub profile_link: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AllianceDetail {
+    pub alliance_name: String,
+    pub alliance_id: Option<i32>,
+    pub member_count: i32,
+    pub village_count: i32,
+    pub total_population: i64,
+    pub alliance_link: Option<String>,
+    pub members: Vec<AllianceMember>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AllianceInfo {
+    pub top_alliances: Vec<AllianceStats>,
+    pub total_alliances: i32,
+}
+
+#[derive(Serialize)]
+pub struct PopulationMover {
+    pub player_name: String,
+    pub previous_population: i64,
+    pub current_population: i64,
+    pub population_change: i64,
+    // "new" players only have villages in the latest snapshot, "vanished" ones only in the
+    // older one; their missing side counts as 0 population
+    pub status: String,
+}
+
+#[derive(Serialize)]
+pub struct PopulationMovers {
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+    pub top_climbers: Vec<PopulationMover>,
+    pub top_fallers: Vec<PopulationMover>,
+}
+
+#[derive(Serialize)]
+pub struct RegionStats {
+    pub column: i32, // From the west edge
+    pub row: i32,    // From the south edge
+    pub min_x: i32,
+    pub max_x: i32,
+    pub min_y: i32,
+    pub max_y: i32,
+    pub village_count: i32,
+    pub total_population: i64,
+    // The alliance with the most population in the region, Natars and alliance-less
+    // villages aside
+    pub dominant_alliance: Option<String>,
+    pub dominant_alliance_population: i64,
+}
+
+#[derive(Serialize)]
+pub struct QuadrantStats {
+    pub quadrant: String,
+    pub village_count: i32,
+    pub total_population: i64,
+}
+
+#[derive(Serialize)]
+pub struct CapitalGroupStats {
+    pub group: String, // "capital", "other", or "unknown" when the dump has no capital column
+    pub village_count: i32,
+    pub total_population: i64,
+}
+
+#[derive(Serialize)]
+pub struct CapitalStats {
+    pub groups: Vec<CapitalGroupStats>,
+    pub largest_capitals: Vec<MapData>,
+}
+
+// Headline numbers for a dashboard; date is None until the server has data
+#[derive(Serialize)]
+pub struct SummaryStats {
+    pub date: Option<chrono::NaiveDate>,
+    pub total_villages: i64,
+    pub total_population: i64,
+    pub player_count: i64,
+    pub alliance_count: i64,
+    pub tribe_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct HeatmapCell {
+    pub cx: i32,
+    pub cy: i32,
+    pub count: i32,
+    pub total_population: i64,
+}
+
+#[derive(Serialize)]
+pub struct PopulationBand {
+    pub min_population: i32,
+    pub max_population: Option<i32>, // Exclusive; None for the open-ended top band
+    pub village_count: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AfkSearchParams {
+    pub quadrant: String, // "NE", "SE", "SW", "NW", or "ALL"
+    pub days: i32, // 1..=MAX_AFK_DAYS
+    pub min_population: Option<i32>, // Compared against the latest snapshot's population
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+pub const MAX_AFK_DAYS: i32 = 10;
+pub const MAX_AFK_PAGE_SIZE: i64 = 5000;
+
+impl AfkSearchParams {
+    // Checked before any query runs, so bad input never reaches the SQL builder
+    pub fn validate(&self) -> std::result::Result<(), InvalidSearchParams> {
+        if self.quadrant != ALL_QUADRANTS && !QUADRANTS.contains(&self.quadrant.as_str()) {
+            return Err(InvalidSearchParams(format!(
+                "quadrant must be one of NE, SE, SW, NW, ALL (got \"{}\")",
+                self.quadrant
+            )));
+        }
+
+        if !(1..=MAX_AFK_DAYS).contains(&self.days) {
+            return Err(InvalidSearchParams(format!(
+                "days must be between 1 and {} (got {})",
+                MAX_AFK_DAYS, self.days
+            )));
+        }
+
+        if self.min_population.is_some_and(|min_population| min_population < 0) {
+            return Err(InvalidSearchParams("min_population must be non-negative".to_string()));
+        }
+
+        if self.limit.is_some_and(|limit| !(1..=MAX_AFK_PAGE_SIZE).contains(&limit)) {
+            return Err(InvalidSearchParams(format!("limit must be between 1 and {}", MAX_AFK_PAGE_SIZE)));
+        }
+
+        if self.offset.is_some_and(|offset| offset < 0) {
+            return Err(InvalidSearchParams("offset must be non-negative".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidSearchParams(pub String);
+
+impl std::fmt::Display for InvalidSearchParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSearchParams {}
+
+pub struct AfkVillagePage {
+    pub villages: Vec<AfkVillage>,
+    pub total: i64,
+}
+
+fn get_tribe_name(tribe_id: i32) -> String {
+    match tribe_id {
+        1 => "Romans".to_string(),
+        2 => "Teutons".to_string(),
+        3 => "Gauls".to_string(),
+        4 => "Nature".to_string(),
+        5 => "Natars".to_string(),
+        6 => "Egyptians".to_string(),
+        7 => "Huns".to_string(),
+        _ => format!("Tribe {}", tribe_id),
+    }
+}
+
+// Resolves a tribe given either as its numeric tid or by name (case-insensitive)
+pub fn parse_tribe(value: &str) -> Option<i32> {
+    let value = value.trim();
+
+    if let Ok(tribe_id) = value.parse::<i32>() {
+        return (tribe_id > 0).then_some(tribe_id);
+    }
+
+    (1..=7).find(|tribe_id| get_tribe_name(*tribe_id).eq_ignore_ascii_case(value))
+}
+
+// Who the player and alliance aggregates count. Villages without an owner never form a
+// player; the Natars and nature (tid 4) are left out unless `include_natars` is set, for
+// analysts tracking the Natars' villages and wonders in the endgame.
+fn player_filter(include_natars: bool) -> String {
+    if include_natars {
+        "player IS NOT NULL AND player != ''".to_string()
+    } else {
+        format!("player IS NOT NULL AND player != '' AND player != 'Natars' AND {}", not_nature("tid"))
+    }
+}
+
+fn alliance_filter(include_natars: bool) -> &'static str {
+    if include_natars {
+        "alliance IS NOT NULL AND alliance != ''"
+    } else {
+        "alliance IS NOT NULL AND alliance != '' AND alliance != 'Natars'"
+    }
+}
+
+// Every player in the latest snapshot, grouped like the world info top 10
+pub async fn get_players(
+    pool: &PgPool,
+    server_id: i32,
+    sort: PlayerSort,
+    page: Pagination,
+    include_natars: bool,
+) -> Result<PlayerPage> {
+    let _timer = QueryTimer::start("players");
+    let Some(table_name) = get_latest_table_for_server(pool, server_id).await? else {
+        return Ok(PlayerPage { players: Vec::new(), total: 0 });
+    };
+
+    let grouped = format!(
+        "SELECT player, alliance, uid, aid, COUNT(*) as village_count, SUM(population) as total_population
+         FROM {}
+         WHERE server_id = $1 AND {}
+         GROUP BY player, alliance, uid, aid",
+        table_name,
+        player_filter(include_natars)
+    );
+
+    let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM ({}) players", grouped))
+        .bind(server_id)
+        .fetch_one(pool)
+        .await?;
+
+    let query = format!("{}{} LIMIT $2 OFFSET $3", grouped, sort.order_by_clause());
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .bind(page.limit)
+        .bind(page.offset)
+        .fetch_all(pool)
+        .await?;
+
+    let base_url = get_server(pool, server_id).await?.map(|server| server_base_url(&server.url));
+    let players = rows
+        .iter()
+        .map(|row| PlayerStats {
+            player_name: row.get("player"),
+            village_count: row.get::<i64, _>("village_count") as i32,
+            total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
+            alliance: row.get("alliance"),
+            profile_link: profile_link(base_url.as_deref(), row.get("uid")),
+            alliance_link: alliance_link(base_url.as_deref(), row.get("aid")),
+        })
+        .collect();
+
+    Ok(PlayerPage { players, total })
+}
+
+// Strips map.sql from a server URL, leaving the game root that profile pages hang off
+fn server_base_url(url: &str) -> String {
+    let base_url = url.trim_end_matches("/map.sql").trim_end_matches("map.sql");
+    base_url.trim_end_matches('/').to_string()
+}
+
+fn profile_link(base_url: Option<&str>, uid: Option<i32>) -> Option<String> {
+    Some(format!("{}/profile/{}", base_url?, uid?))
+}
+
+fn alliance_link(base_url: Option<&str>, aid: Option<i32>) -> Option<String> {
+    Some(format!("{}/alliance/{}", base_url?, aid?))
+}
+
+pub async fn get_world_info(
+    pool: &PgPool,
+    cache: &WorldInfoCache,
+    include_natars: bool,
+    default_map_size: i32,
+) -> Result<WorldInfo> {
+    // Get the active server
+    let active_server = get_active_server(pool).await?;
+    
+    if let Some(server) = active_server {
+        // Only the default view is cached
+        if include_natars {
+            return get_world_info_for_server(pool, server.id, true).await;
+        }
+
+
+        // Reuse the cached aggregates while the snapshot they were computed from is
+        // unchanged. The version is read from the same pool as the aggregates, so a
+        // lagging replica's result stops matching once the replica catches up.
+        match get_snapshot_version_for_server(pool, &server, default_map_size).await? {
+            Some(version) => {
+                if let Some(world_info) = cache.get(server.id, &version) {
+                    return Ok(world_info);
+                }
+
+                let world_info = get_world_info_for_server(pool, server.id, false).await?;
+                cache.insert(server.id, version, world_info.clone());
+                Ok(world_info)
+            },
+            None => get_world_info_for_server(pool, server.id, false).await,
+        }
+    } else {
+        Err(NoActiveServer.into())
+    }
+}
+
+pub async fn get_world_info_for_server(pool: &PgPool, server_id: i32, include_natars: bool) -> Result<WorldInfo> {
+    let _timer = QueryTimer::start("world_info");
+    // Get the latest table for this server
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    
+    if available_dates.is_empty() {
+        return Ok(WorldInfo {
+            tribe_stats: Vec::new(),
+            top_players: Vec::new(),
+            total_villages: 0,
+            total_population: 0,
+        });
+    }
+    
+    let latest_date = available_dates[0].0;
+    let table_name = get_table_name_for_server_and_date(server_id, latest_date);
+    
+    // Check if table exists
+    let table_exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT FROM information_schema.tables WHERE table_schema = 'public' AND table_name = $1)"
+    )
+    .bind(&table_name)
+    .fetch_one(pool)
+    .await?;
+    
+    if !table_exists {
+        return Ok(WorldInfo {
+            tribe_stats: Vec::new(),
+            top_players: Vec::new(),
+            total_villages: 0,
+            total_population: 0,
+        });
+    }
+    
+    // Get the active server for profile links
+    let active_server = get_active_server(pool).await?;
+    let server_base_url = active_server.as_ref().map(|server| server_base_url(&server.url));
+    
+    // Get tribe statistics
+    let tribe_query = format!(
+        "SELECT tid, COUNT(*) as village_count, SUM(population) as total_population 
+         FROM {} 
+         WHERE server_id = $1 AND tid IS NOT NULL 
+         GROUP BY tid 
+         ORDER BY total_population DESC",
+        table_name
+    );
+    
+    let tribe_rows = sqlx::query(&tribe_query)
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+    
+    let tribe_stats: Vec<TribeStats> = tribe_rows
+        .into_iter()
+        .map(|row| {
+            let tribe_id: i32 = row.get("tid");
+            TribeStats {
+                tribe_id,
+                tribe_name: get_tribe_name(tribe_id),
+                village_count: row.get::<i64, _>("village_count") as i32,
+                total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
+            }
+        })
+        .collect();
+    
+    // Get top 10 players by population (excluding Natars and nature unless asked for)
+    let player_query = format!(
+        "SELECT player, alliance, uid, aid, COUNT(*) as village_count, SUM(population) as total_population 
+         FROM {} 
+         WHERE server_id = $1 AND {}
+         GROUP BY player, alliance, uid, aid 
+         ORDER BY total_population DESC 
+         LIMIT 10",
+        table_name,
+        player_filter(include_natars)
+    );
+    
+    let player_rows = sqlx::query(&player_query)
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+    
+    let top_players: Vec<PlayerStats> = player_rows
+        .into_iter()
+        .map(|row| {
+            let uid: Option<i32> = row.get("uid");
+            let aid: Option<i32> = row.get("aid");
+            let profile_link = profile_link(server_base_url.as_deref(), uid);
+            let alliance_link = alliance_link(server_base_url.as_deref(), aid);
+            
+            PlayerStats {
+                player_name: row.get("player"),
+                village_count: row.get::<i64, _>("village_count") as i32,
+                total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
+                alliance: row.get("alliance"),
+                profile_link,
+                alliance_link,
+            }
+        })
+        .collect();
+    
+    // Get total statistics
+    let total_query = format!(
+        "SELECT COUNT(*) as total_villages, SUM(population) as total_population 
+         FROM {} 
+         WHERE server_id = $1",
+        table_name
+    );
+    
+    let total_row = sqlx::query(&total_query)
+        .bind(server_id)
+        .fetch_one(pool)
+        .await?;
+    
+    let total_villages = total_row.get::<i64, _>("total_villages") as i32;
+    let total_population = total_row.get::<Option<i64>, _>("total_population").unwrap_or(0);
+    
+    Ok(WorldInfo {
+        tribe_stats,
+        top_players,
+        total_villages,
+        total_population,
+    })
+}
+
+// Every village the player owns in the active server's latest snapshot, largest first.
+// None when the player has no villages there.
+pub async fn get_player_detail(pool: &PgPool, player_name: &str) -> Result<Option<PlayerDetail>> {
+    let _timer = QueryTimer::start("player_detail");
+    let server = get_active_server(pool).await?.ok_or(NoActiveServer)?;
+
+    let Some(table_name) = get_latest_table_for_server(pool, server.id).await? else {
+        return Ok(None);
+    };
+
+    let query = format!(
+        "SELECT id, village, x, y, population, player, alliance, worldid, tid, uid, aid
+         FROM {}
+         WHERE server_id = $1 AND player = $2
+         ORDER BY population DESC, id",
+        table_name
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server.id)
+        .bind(player_name)
+        .fetch_all(pool)
+        .await?;
+
+    let Some(first) = rows.first() else {
+        return Ok(None);
+    };
+
+    let base_url = server_base_url(&server.url);
+    let profile_link = profile_link(Some(&base_url), first.get("uid"));
+    // Players without an alliance have an empty name and aid 0 in map.sql
+    let alliance: Option<String> = first.get::<Option<String>, _>("alliance").filter(|alliance| !alliance.is_empty());
+    let alliance_link = alliance
+        .as_ref()
+        .and_then(|_| alliance_link(Some(&base_url), first.get("aid")));
+
+    let villages: Vec<MapData> = rows
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(PlayerDetail {
+        player_name: player_name.to_string(),
+        alliance,
+        total_population: villages.iter().map(|village| village.population as i64).sum(),
+        village_count: villages.len() as i32,
+        villages,
+        profile_link,
+        alliance_link,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct WorldWonder {
+    pub x: i32,
+    pub y: i32,
+    pub village_name: String,
+    pub ww_name: Option<String>,
+    pub player: Option<String>,
+    pub alliance: Option<String>,
+    pub population: i32,
+    // Change since the previous snapshot; None when there is no earlier snapshot or the
+    // wonder village was not in it
+    pub population_change: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct WorldWonders {
+    pub date: Option<chrono::NaiveDate>,
+    pub previous_date: Option<chrono::NaiveDate>,
+    pub wonders: Vec<WorldWonder>,
+}
+
+pub async fn get_world_wonders(pool: &PgPool, server_id: i32) -> Result<WorldWonders> {
+    let _timer = QueryTimer::start("world_wonders");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let Some(&(date, _)) = available_dates.first() else {
+        return Ok(WorldWonders { date: None, previous_date: None, wonders: Vec::new() });
+    };
+    let previous_date = available_dates.get(1).map(|&(date, _)| date);
+
+    let table_name = get_table_name_for_server_and_date(server_id, date);
+    let previous_population = match previous_date {
+        Some(previous_date) => format!(
+            "(SELECT p.population FROM {} p WHERE p.server_id = l.server_id AND p.x = l.x AND p.y = l.y)",
+            get_table_name_for_server_and_date(server_id, previous_date)
+        ),
+        None => "NULL::INTEGER".to_string(),
+    };
+
+    let query = format!(
+        "SELECT l.x, l.y, l.village, l.wwname, l.player, l.alliance, l.population,
+                l.population - {} as population_change
+         FROM {} l
+         WHERE l.server_id = $1 AND l.isWW
+         ORDER BY l.population DESC, l.id",
+        previous_population, table_name
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+
+    let wonders = rows
+        .into_iter()
+        .map(|row| WorldWonder {
+            x: row.get("x"),
+            y: row.get("y"),
+            village_name: row.get("village"),
+            ww_name: row.get("wwname"),
+            player: row.get("player"),
+            alliance: row.get("alliance"),
+            population: row.get("population"),
+            population_change: row.get("population_change"),
+        })
+        .collect();
+
+    Ok(WorldWonders { date: Some(date), previous_date, wonders })
+}
+
+// Buckets the server's latest snapshot into cell x cell squares. Cell (cx, cy) covers
+// x in [cx * cell, (cx + 1) * cell), so negative coordinates floor away from zero.
+// Only non-empty cells are returned.
+pub async fn get_village_heatmap(pool: &PgPool, server_id: i32, cell: i32) -> Result<Vec<HeatmapCell>> {
+    let _timer = QueryTimer::start("village_heatmap");
+    let Some(table_name) = get_latest_table_for_server(pool, server_id).await? else {
+        return Ok(Vec::new());
+    };
+
+    let query = format!(
+        "SELECT FLOOR(x::FLOAT8 / $2::INTEGER)::INTEGER as cx, FLOOR(y::FLOAT8 / $2::INTEGER)::INTEGER as cy,
+                COUNT(*) as village_count, SUM(population) as total_population
+         FROM {}
+         WHERE server_id = $1
+         GROUP BY cx, cy
+         ORDER BY cy, cx",
+        table_name
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .bind(cell)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| HeatmapCell {
+            cx: row.get("cx"),
+            cy: row.get("cy"),
+            count: row.get::<i64, _>("village_count") as i32,
+            total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
+        })
+        .collect())
+}
+
+// Counts villages per population band of the server's latest snapshot. `bounds` are the
+// ascending, positive upper limits of every band but the last, so [100, 250] yields
+// 0-100, 100-250 and 250+. Empty bands are included.
+pub async fn get_population_histogram(pool: &PgPool, server_id: i32, bounds: &[i32]) -> Result<Vec<PopulationBand>> {
+    let _timer = QueryTimer::start("population_histogram");
+    let thresholds: Vec<i32> = std::iter::once(0).chain(bounds.iter().copied()).collect();
+
+    let mut bands: Vec<PopulationBand> = thresholds
+        .iter()
+        .enumerate()
+        .map(|(index, min_population)| PopulationBand {
+            min_population: *min_population,
+            max_population: thresholds.get(index + 1).copied(),
+            village_count: 0,
+        })
+        .collect();
+
+    let Some(table_name) = get_latest_table_for_server(pool, server_id).await? else {
+        return Ok(bands);
+    };
+
+    // width_bucket against an explicit threshold array returns 1 for the first band
+    let query = format!(
+        "SELECT width_bucket(population, $2::INTEGER[]) as band, COUNT(*) as village_count
+         FROM {}
+         WHERE server_id = $1
+         GROUP BY band",
+        table_name
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .bind(&thresholds)
+        .fetch_all(pool)
+        .await?;
+
+    for row in rows {
+        let band: i32 = row.get("band");
+        // Band 0 would be a negative population, which map.sql never contains
+        if let Some(entry) = usize::try_from(band - 1).ok().and_then(|index| bands.get_mut(index)) {
+            entry.village_count = row.get::<i64, _>("village_count") as i32;
+        }
+    }
+
+    Ok(bands)
+}
+
+pub const QUADRANTS: [&str; 4] = ["NE", "SE", "SW", "NW"];
+
+// Accepted by the AFK search in place of a quadrant to cover the whole map
+pub const ALL_QUADRANTS: &str = "ALL";
+
+// SQL condition selecting the villages of `alias` that lie in a quadrant. The axes
+// themselves belong to the north and east halves.
+fn quadrant_condition(quadrant: &str, alias: &str) -> Option<String> {
+    if quadrant == ALL_QUADRANTS {
+        return Some("TRUE".to_string());
+    }
+
+    let (x_condition, y_condition) = match quadrant {
+        "NE" => (">= 0", ">= 0"),
+        "SE" => (">= 0", "< 0"),
+        "SW" => ("< 0", "< 0"),
+        "NW" => ("< 0", ">= 0"),
+        _ => return None,
+    };
+
+    Some(format!("{alias}.x {x_condition} AND {alias}.y {y_condition}"))
+}
+
+// Village count and population per quadrant of the server's latest snapshot, always
+// listing all four quadrants in QUADRANTS order
+pub async fn get_quadrant_stats(pool: &PgPool, server_id: i32) -> Result<Vec<QuadrantStats>> {
+    let _timer = QueryTimer::start("quadrant_stats");
+    let mut stats: Vec<QuadrantStats> = QUADRANTS
+        .iter()
+        .map(|quadrant| QuadrantStats {
+            quadrant: quadrant.to_string(),
+            village_count: 0,
+            total_population: 0,
+        })
+        .collect();
+
+    let Some(table_name) = get_latest_table_for_server(pool, server_id).await? else {
+        return Ok(stats);
+    };
+
+    let cases: String = QUADRANTS
+        .iter()
+        .filter_map(|quadrant| Some(format!(" WHEN {} THEN '{}'", quadrant_condition(quadrant, "v")?, quadrant)))
+        .collect();
+    let query = format!(
+        "SELECT CASE{} END as quadrant, COUNT(*) as village_count, SUM(population) as total_population
+         FROM {} v
+         WHERE server_id = $1
+         GROUP BY 1",
+        cases, table_name
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+
+    for row in rows {
+        let quadrant: String = row.get("quadrant");
+        if let Some(entry) = stats.iter_mut().find(|entry| entry.quadrant == quadrant) {
+            entry.village_count = row.get::<i64, _>("village_count") as i32;
+            entry.total_population = row.get::<Option<i64>, _>("total_population").unwrap_or(0);
+        }
+    }
+
+    Ok(stats)
+}
+
+// Villages and population cover the whole snapshot. Players, alliances and tribes only
+// count real accounts, leaving out the Natars and nature like the leaderboards do unless
+// `include_natars` is set.
+pub async fn get_summary_stats(pool: &PgPool, server_id: i32, include_natars: bool) -> Result<SummaryStats> {
+    let _timer = QueryTimer::start("summary_stats");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let Some((date, _)) = available_dates.first() else {
+        return Ok(SummaryStats {
+            date: None,
+            total_villages: 0,
+            total_population: 0,
+            player_count: 0,
+            alliance_count: 0,
+            tribe_count: 0,
+        });
+    };
+
+    let query = format!(
+        "SELECT COUNT(*) as total_villages,
+                SUM(population) as total_population,
+                COUNT(DISTINCT player) FILTER (WHERE {}) as player_count,
+                COUNT(DISTINCT alliance) FILTER (WHERE {}) as alliance_count,
+                COUNT(DISTINCT tid) FILTER (WHERE {}) as tribe_count
+         FROM {}
+         WHERE server_id = $1",
+        player_filter(include_natars),
+        alliance_filter(include_natars),
+        if include_natars { "tid IS NOT NULL".to_string() } else { format!("tid NOT IN ({}, {})", NATURE_TRIBE_ID, NATARS_TRIBE_ID) },
+        get_table_name_for_server_and_date(server_id, *date)
+    );
+
+    let row = sqlx::query(&query)
+        .bind(server_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(SummaryStats {
+        date: Some(*date),
+        total_villages: row.get("total_villages"),
+        total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
+        player_count: row.get("player_count"),
+        alliance_count: row.get("alliance_count"),
+        tribe_count: row.get("tribe_count"),
+    })
+}
+
+const CAPITAL_GROUPS: [&str; 3] = ["capital", "other", "unknown"];
+const LARGEST_CAPITALS: i64 = 10;
+
+// capital is stored as the dump's text (TRUE/FALSE, or 1/0 in some exports). Older dumps
+// leave it out entirely, so NULL counts as unknown rather than as a non-capital.
+const CAPITAL_GROUP_CASE: &str =
+    "CASE WHEN capital IS NULL THEN 'unknown' WHEN UPPER(capital) IN ('TRUE', 'T', '1') THEN 'capital' ELSE 'other' END";
+
+pub async fn get_capital_stats(pool: &PgPool, server_id: i32) -> Result<CapitalStats> {
+    let _timer = QueryTimer::start("capital_stats");
+    let mut groups: Vec<CapitalGroupStats> = CAPITAL_GROUPS
+        .iter()
+        .map(|group| CapitalGroupStats {
+            group: group.to_string(),
+            village_count: 0,
+            total_population: 0,
+        })
+        .collect();
+
+    let Some(table_name) = get_latest_table_for_server(pool, server_id).await? else {
+        return Ok(CapitalStats { groups, largest_capitals: Vec::new() });
+    };
+
+    let group_query = format!(
+        "SELECT {} as capital_group, COUNT(*) as village_count, SUM(population) as total_population
+         FROM {}
+         WHERE server_id = $1
+         GROUP BY 1",
+        CAPITAL_GROUP_CASE, table_name
+    );
+
+    let rows = sqlx::query(&group_query)
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+
+    for row in rows {
+        let group: String = row.get("capital_group");
+        if let Some(entry) = groups.iter_mut().find(|entry| entry.group == group) {
+            entry.village_count = row.get::<i64, _>("village_count") as i32;
+            entry.total_population = row.get::<Option<i64>, _>("total_population").unwrap_or(0);
+        }
+    }
+
+    let largest_query = format!(
+        "SELECT id, village, x, y, population, player, alliance, worldid, tid
+         FROM {}
+         WHERE server_id = $1 AND {} = 'capital'
+         ORDER BY population DESC, id
+         LIMIT $2",
+        table_name, CAPITAL_GROUP_CASE
+    );
+
+    let largest_capitals = sqlx::query(&largest_query)
+        .bind(server_id)
+        .bind(LARGEST_CAPITALS)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(CapitalStats { groups, largest_capitals })
+}
+
+// Tiles the server's map into region_size-wide regions (see geo::region_of) and totals
+// the latest snapshot per region. Every region is returned, empty ones included, in
+// row-major order.
+pub async fn get_region_stats(
+    pool: &PgPool,
+    server_id: i32,
+    region_size: i32,
+    default_map_size: i32,
+) -> Result<Vec<RegionStats>> {
+    let _timer = QueryTimer::start("region_stats");
+    let map_size = get_map_size_for_server(pool, server_id, default_map_size).await?;
+    let per_axis = geo::regions_per_axis(map_size, region_size);
+
+    #[derive(Default)]
+    struct RegionTotals {
+        village_count: i32,
+        total_population: i64,
+        alliances: HashMap<String, i64>,
+    }
+    let mut totals: HashMap<geo::Region, RegionTotals> = HashMap::new();
+
+    if let Some(table_name) = get_latest_table_for_server(pool, server_id).await? {
+        let query = format!("SELECT x, y, population, alliance FROM {} WHERE server_id = $1", table_name);
+        let mut rows = sqlx::query(&query).bind(server_id).fetch(pool);
+
+        while let Some(row) = rows.try_next().await? {
+            let region = geo::region_of(row.get("x"), row.get("y"), map_size, region_size);
+            let population = row.get::<i32, _>("population") as i64;
+            let entry = totals.entry(region).or_default();
+            entry.village_count += 1;
+            entry.total_population += population;
+
+            if let Some(alliance) = row.get::<Option<String>, _>("alliance").filter(|alliance| !alliance.is_empty() && alliance != "Natars") {
+                *entry.alliances.entry(alliance).or_default() += population;
+            }
+        }
+    }
+
+    let mut stats = Vec::with_capacity((per_axis * per_axis) as usize);
+    for row in 0..per_axis {
+        for column in 0..per_axis {
+            let region = geo::Region { column, row };
+            let (min_x, max_x, min_y, max_y) = region.bounds(map_size, region_size);
+            let region_totals = totals.remove(&region).unwrap_or_default();
+            // Ties go to the alphabetically first alliance so results are stable
+            let dominant = region_totals
+                .alliances
+                .into_iter()
+                .max_by(|(a_name, a_pop), (b_name, b_pop)| a_pop.cmp(b_pop).then_with(|| b_name.cmp(a_name)));
+
+            stats.push(RegionStats {
+                column,
+                row,
+                min_x,
+                max_x,
+                min_y,
+                max_y,
+                village_count: region_totals.village_count,
+                total_population: region_totals.total_population,
+                dominant_alliance_population: dominant.as_ref().map_or(0, |(_, population)| *population),
+                dominant_alliance: dominant.map(|(alliance, _)| alliance),
+            });
+        }
+    }
+
+    Ok(stats)
+}
+
+pub async fn find_afk_villages(pool: &PgPool, params: AfkSearchParams) -> Result<AfkVillagePage> {
+    // Get the active server
+    let active_server = get_active_server(pool).await?;
+    
+    if let Some(server) = active_server {
+        find_afk_villages_for_server(pool, server.id, params).await
+    } else {
+        Err(NoActiveServer.into())
+    }
+}
+
+pub async fn find_afk_villages_for_server(pool: &PgPool, server_id: i32, params: AfkSearchParams) -> Result<AfkVillagePage> {
+    let _timer = QueryTimer::start("afk_villages");
+    params.validate()?;
+
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    let empty_page = AfkVillagePage { villages: Vec::new(), total: 0 };
+    
+    if available_dates.len() < (params.days as usize + 1) {
+        return Ok(empty_page); // Not enough historical data
+    }
+    
     let latest_date = available_dates[0].0;
     let comparison_date = available_dates[params.days as usize].0;
     
@@ -1005,17 +4699,14 @@ pub async fn find_afk_villages_for_server(pool: &PgPool, server_id: i32, params:
     .await?;
     
     if !latest_exists || !comparison_exists {
-        return Ok(Vec::new());
+        return Ok(empty_page);
     }
     
+    let min_population = params.min_population.unwrap_or(0);
+
     // Determine quadrant coordinates
-    let (x_condition, y_condition) = match params.quadrant.as_str() {
-        "NE" => ("l.x >= 0", "l.y >= 0"),
-        "SE" => ("l.x >= 0", "l.y < 0"),
-        "SW" => ("l.x < 0", "l.y < 0"),
-        "NW" => ("l.x < 0", "l.y >= 0"),
-        _ => return Err(anyhow::anyhow!("Invalid quadrant: {}", params.quadrant)),
-    };
+    let quadrant_condition = quadrant_condition(&params.quadrant, "l")
+        .ok_or_else(|| anyhow::anyhow!("Invalid quadrant: {}", params.quadrant))?;
     
     // Find villages that haven't grown in population
     let village_query = format!(
@@ -1028,15 +4719,18 @@ pub async fn find_afk_villages_for_server(pool: &PgPool, server_id: i32, params:
         AND l.player IS NOT NULL 
         AND l.player != '' 
         AND l.player != 'Natars'
+        AND {}
         AND c.player = l.player
         AND l.population <= c.population
-        AND {} AND {}
+        AND l.population >= $2
+        AND {}
         "#,
-        latest_table, comparison_table, x_condition, y_condition
+        latest_table, comparison_table, not_nature("l.tid"), quadrant_condition
     );
     
     let village_rows = sqlx::query(&village_query)
         .bind(server_id)
+        .bind(min_population)
         .fetch_all(pool)
         .await?;
     
@@ -1053,9 +4747,10 @@ pub async fn find_afk_villages_for_server(pool: &PgPool, server_id: i32, params:
                 COALESCE(SUM(l.population), 0) as latest_total,
                 COALESCE(SUM(c.population), 0) as comparison_total
             FROM {} l
-            LEFT JOIN {} c ON l.player = c.player AND l.server_id = c.server_id
+            LEFT JOIN {} c ON l.player = c.player AND l.server_id = c.server_id AND c.population >= $3
             WHERE l.server_id = $1 
             AND l.player = $2
+            AND l.population >= $3
             GROUP BY l.player
             "#,
             latest_table, comparison_table
@@ -1064,6 +4759,7 @@ pub async fn find_afk_villages_for_server(pool: &PgPool, server_id: i32, params:
         let growth_row = sqlx::query(&player_growth_query)
             .bind(server_id)
             .bind(&player_name)
+            .bind(min_population)
             .fetch_optional(pool)
             .await?;
         
@@ -1090,23 +4786,321 @@ pub async fn find_afk_villages_for_server(pool: &PgPool, server_id: i32, params:
     }
     
     // Sort by population descending
-    afk_villages.sort_by(|a, b| b.population.cmp(&a.population));
+    afk_villages.sort_by_key(|v| std::cmp::Reverse(v.population));
     
-    Ok(afk_villages)
+    // The growth check runs per player in Rust, so paging happens after it
+    let total = afk_villages.len() as i64;
+    let villages = afk_villages
+        .into_iter()
+        .skip(params.offset.unwrap_or(0).max(0) as usize)
+        .take(params.limit.map_or(usize::MAX, |limit| limit.max(0) as usize))
+        .collect();
+
+    Ok(AfkVillagePage { villages, total })
+}
+
+#[derive(Serialize)]
+pub struct FarmCandidate {
+    pub village_name: String,
+    pub x: i32,
+    pub y: i32,
+    pub population: i32,
+    pub player_name: String,
+    pub alliance: Option<String>,
+    pub distance: f64,
+}
+
+pub struct FarmSearch {
+    pub x: i32,
+    pub y: i32,
+    pub radius: i32,
+    pub max_population: Option<i32>,
+    pub days: usize,
+    pub limit: i64,
+}
+
+// AFK villages within `radius` fields of (x, y), nearest first and smallest first among
+// equals. A village qualifies under the same rules as the AFK search: same owner and no
+// growth since `days` snapshots ago, and the owner's whole account did not grow either.
+// Empty when fewer than days + 1 snapshots exist.
+pub async fn find_farms(
+    pool: &PgPool,
+    server_id: i32,
+    search: &FarmSearch,
+    default_map_size: i32,
+) -> Result<Vec<FarmCandidate>> {
+    let _timer = QueryTimer::start("farms");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let (Some((latest_date, _)), Some((comparison_date, _))) = (available_dates.first(), available_dates.get(search.days)) else {
+        return Ok(Vec::new()); // Not enough historical data
+    };
+    let latest_table = get_table_name_for_server_and_date(server_id, *latest_date);
+    let comparison_table = get_table_name_for_server_and_date(server_id, *comparison_date);
+    let map_size = get_map_size_for_server(pool, server_id, default_map_size).await?;
+
+    let player_totals = |table_name: &str| format!(
+        "SELECT player, SUM(population) as total FROM {} WHERE server_id = $1 GROUP BY player",
+        table_name
+    );
+    let distance_squared = toroidal_distance_squared_between_sql(("l.x", "l.y"), ("$2", "$3"), "$4");
+
+    let query = format!(
+        r#"
+        WITH latest_totals AS ({}), comparison_totals AS ({})
+        SELECT l.village, l.x, l.y, l.population, l.player, l.alliance
+        FROM {} l
+        JOIN {} c ON l.x = c.x AND l.y = c.y AND l.server_id = c.server_id
+        JOIN latest_totals lt ON lt.player = l.player
+        JOIN comparison_totals ct ON ct.player = l.player
+        WHERE l.server_id = $1
+        AND l.player IS NOT NULL
+        AND l.player != ''
+        AND l.player != 'Natars'
+        AND {}
+        AND c.player = l.player
+        AND l.population <= c.population
+        AND lt.total <= ct.total
+        AND ($6::INTEGER IS NULL OR l.population <= $6)
+        AND {} <= $5
+        ORDER BY {}, l.population, l.id
+        LIMIT $7
+        "#,
+        player_totals(&latest_table),
+        player_totals(&comparison_table),
+        latest_table,
+        comparison_table,
+        not_nature("l.tid"),
+        distance_squared,
+        distance_squared
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .bind(search.x)
+        .bind(search.y)
+        .bind(map_size)
+        .bind(search.radius as i64 * search.radius as i64)
+        .bind(search.max_population)
+        .bind(search.limit)
+        .fetch_all(pool)
+        .await?;
+
+    let farms = rows
+        .iter()
+        .map(|row| {
+            let x: i32 = row.get("x");
+            let y: i32 = row.get("y");
+            FarmCandidate {
+                village_name: row.get("village"),
+                x,
+                y,
+                population: row.get("population"),
+                player_name: row.get("player"),
+                alliance: row.get("alliance"),
+                distance: geo::travel_distance(search.x, search.y, x, y, map_size),
+            }
+        })
+        .collect();
+
+    Ok(farms)
+}
+
+// Players whose total population never grew between any two consecutive snapshots of
+// the last `days` days and who had villages in every one of them. Unlike the per-village
+// AFK search this looks at the whole account, so a player growing elsewhere is not
+// listed. Largest players first; empty when fewer than days + 1 snapshots exist.
+pub async fn find_inactive_players(pool: &PgPool, server_id: i32, days: usize, min_pop: i64) -> Result<Vec<InactivePlayer>> {
+    let _timer = QueryTimer::start("inactive_players");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    if available_dates.len() < days + 1 {
+        return Ok(Vec::new()); // Not enough historical data
+    }
+
+    // age 0 is the latest snapshot
+    let window: Vec<String> = available_dates[..=days]
+        .iter()
+        .map(|(date, _)| get_table_name_for_server_and_date(server_id, *date))
+        .collect();
+    let latest_table = &window[0];
+
+    let totals = window
+        .iter()
+        .enumerate()
+        .map(|(age, table_name)| format!(
+            "SELECT {} as age, player, SUM(population) as total FROM {}
+             WHERE server_id = $1 AND {}
+             GROUP BY player",
+            age, table_name, player_filter(false)
+        ))
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+
+    let player_query = format!(
+        r#"
+        WITH totals AS ({}),
+        steps AS (
+            SELECT player, age, total, LAG(total) OVER (PARTITION BY player ORDER BY age DESC) as previous
+            FROM totals
+        )
+        SELECT player,
+               MAX(total) FILTER (WHERE age = 0) as total_population,
+               MAX(total) FILTER (WHERE age = 0) - MAX(total) FILTER (WHERE age = $2) as population_change
+        FROM steps
+        GROUP BY player
+        HAVING COUNT(*) = $2 + 1
+        AND BOOL_AND(previous IS NULL OR total <= previous)
+        AND MAX(total) FILTER (WHERE age = 0) >= $3
+        "#,
+        totals
+    );
+
+    let player_rows = sqlx::query(&player_query)
+        .bind(server_id)
+        .bind(days as i32)
+        .bind(min_pop)
+        .fetch_all(pool)
+        .await?;
+
+    if player_rows.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let player_names: Vec<String> = player_rows.iter().map(|row| row.get("player")).collect();
+
+    let village_query = format!(
+        "SELECT id, village, x, y, population, player, alliance, worldid, tid, uid
+         FROM {}
+         WHERE server_id = $1 AND player = ANY($2)
+         ORDER BY population DESC, id",
+        latest_table
+    );
+
+    let village_rows = sqlx::query(&village_query)
+        .bind(server_id)
+        .bind(&player_names)
+        .fetch_all(pool)
+        .await?;
+
+    let base_url = get_server(pool, server_id).await?.map(|server| server_base_url(&server.url));
+
+    let mut inactive_players: Vec<InactivePlayer> = player_rows
+        .iter()
+        .map(|row| InactivePlayer {
+            player_name: row.get("player"),
+            alliance: None,
+            total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
+            population_change: row.get::<Option<i64>, _>("population_change").unwrap_or(0),
+            village_count: 0,
+            villages: Vec::new(),
+            profile_link: None,
+        })
+        .collect();
+
+    for row in village_rows {
+        let player_name: String = row.get("player");
+        let Some(player) = inactive_players.iter_mut().find(|player| player.player_name == player_name) else {
+            continue;
+        };
+
+        if player.villages.is_empty() {
+            player.alliance = row.get::<Option<String>, _>("alliance").filter(|alliance| !alliance.is_empty());
+            player.profile_link = profile_link(base_url.as_deref(), row.get("uid"));
+        }
+
+        player.villages.push(MapData::from_row(&row)?);
+        player.village_count += 1;
+    }
+
+    inactive_players.sort_by(|a, b| {
+        b.total_population
+            .cmp(&a.total_population)
+            .then_with(|| a.player_name.cmp(&b.player_name))
+    });
+
+    Ok(inactive_players)
+}
+
+// Compares per-player population in the latest snapshot with the one `days` snapshots
+// earlier, or the oldest stored one when there is less history than that
+pub async fn get_population_movers(pool: &PgPool, server_id: i32, days: usize, limit: i64) -> Result<PopulationMovers> {
+    let _timer = QueryTimer::start("population_movers");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+
+    let (Some(&(to, _)), Some(&(from, _))) = (
+        available_dates.first(),
+        available_dates.get(days).or(available_dates.last()),
+    ) else {
+        return Ok(PopulationMovers { from: None, to: None, top_climbers: Vec::new(), top_fallers: Vec::new() });
+    };
+
+    let player_totals = |date| format!(
+        "SELECT player, SUM(population) as total FROM {}
+         WHERE server_id = $1 AND {}
+         GROUP BY player",
+        get_table_name_for_server_and_date(server_id, date),
+        player_filter(false)
+    );
+    let movers_query = |direction: &str, order: &str| format!(
+        r#"
+        WITH current AS ({}), previous AS ({}),
+        movers AS (
+            SELECT COALESCE(c.player, p.player) as player,
+                   COALESCE(p.total, 0) as previous_population,
+                   COALESCE(c.total, 0) as current_population,
+                   COALESCE(c.total, 0) - COALESCE(p.total, 0) as population_change,
+                   CASE WHEN p.player IS NULL THEN 'new' WHEN c.player IS NULL THEN 'vanished' ELSE 'existing' END as status
+            FROM current c
+            FULL OUTER JOIN previous p ON p.player = c.player
+        )
+        SELECT * FROM movers
+        WHERE population_change {} 0
+        ORDER BY population_change {}, player
+        LIMIT $2
+        "#,
+        player_totals(to), player_totals(from), direction, order
+    );
+
+    let mut movers = Vec::with_capacity(2);
+    for query in [movers_query(">", "DESC"), movers_query("<", "ASC")] {
+        let rows = sqlx::query(&query)
+            .bind(server_id)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+
+        movers.push(
+            rows.into_iter()
+                .map(|row| PopulationMover {
+                    player_name: row.get("player"),
+                    previous_population: row.get("previous_population"),
+                    current_population: row.get("current_population"),
+                    population_change: row.get("population_change"),
+                    status: row.get("status"),
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let top_fallers = movers.pop().unwrap_or_default();
+    let top_climbers = movers.pop().unwrap_or_default();
+    Ok(PopulationMovers { from: Some(from), to: Some(to), top_climbers, top_fallers })
 }
 
-pub async fn get_alliance_info(pool: &PgPool) -> Result<AllianceInfo> {
+pub async fn get_alliance_info(pool: &PgPool, include_natars: bool) -> Result<AllianceInfo> {
     // Get the active server
     let active_server = get_active_server(pool).await?;
     
     if let Some(server) = active_server {
-        get_alliance_info_for_server(pool, server.id).await
+        get_alliance_info_for_server(pool, server.id, include_natars).await
     } else {
-        Err(anyhow::anyhow!("No active server found"))
+        Err(NoActiveServer.into())
     }
 }
 
-pub async fn get_alliance_info_for_server(pool: &PgPool, server_id: i32) -> Result<AllianceInfo> {
+pub async fn get_alliance_info_for_server(pool: &PgPool, server_id: i32, include_natars: bool) -> Result<AllianceInfo> {
+    let _timer = QueryTimer::start("alliance_info");
     let available_dates = get_available_dates_for_server(pool, server_id).await?;
     
     if available_dates.is_empty() {
@@ -1136,22 +5130,14 @@ pub async fn get_alliance_info_for_server(pool: &PgPool, server_id: i32) -> Resu
     
     // Get the active server for alliance links
     let active_server = get_active_server(pool).await?;
-    let server_base_url = if let Some(server) = &active_server {
-        let base_url = server.url.trim_end_matches("/map.sql").trim_end_matches("map.sql");
-        Some(base_url.trim_end_matches('/').to_string())
-    } else {
-        None
-    };
+    let server_base_url = active_server.as_ref().map(|server| server_base_url(&server.url));
     
     // Get current alliance statistics
     let alliance_query = format!(
-        "SELECT alliance, aid, COUNT(DISTINCT uid) as member_count, COUNT(*) as village_count, SUM(population) as total_population
-         FROM {} 
-         WHERE server_id = $1 AND alliance IS NOT NULL AND alliance != '' AND alliance != 'Natars'
-         GROUP BY alliance, aid 
+        "{}
          ORDER BY total_population DESC 
          LIMIT 20",
-        latest_table
+        alliance_aggregate_query(&latest_table, alliance_filter(include_natars))
     );
     
     let alliance_rows = sqlx::query(&alliance_query)
@@ -1159,17 +5145,130 @@ pub async fn get_alliance_info_for_server(pool: &PgPool, server_id: i32) -> Resu
         .fetch_all(pool)
         .await?;
     
-    let mut alliance_stats = Vec::new();
-    
     // Get previous day's data for growth calculation if available
-    let has_previous_data = available_dates.len() > 1;
-    let previous_table = if has_previous_data {
-        Some(get_table_name_for_server_and_date(server_id, available_dates[1].0))
+    let previous_table = previous_snapshot_table(pool, server_id, &available_dates).await?;
+    let alliance_stats = alliance_stats_from_rows(
+        pool,
+        server_id,
+        &alliance_rows,
+        previous_table.as_deref(),
+        server_base_url.as_deref(),
+    ).await?;
+    
+    // Get total number of alliances
+    let total_query = format!(
+        "SELECT COUNT(DISTINCT alliance) as total_alliances
+         FROM {} 
+         WHERE server_id = $1 AND {}",
+        latest_table,
+        alliance_filter(include_natars)
+    );
+    
+    let total_alliances: i64 = sqlx::query_scalar(&total_query)
+        .bind(server_id)
+        .fetch_one(pool)
+        .await?;
+    
+    Ok(AllianceInfo {
+        top_alliances: alliance_stats,
+        total_alliances: total_alliances as i32,
+    })
+}
+
+// Every alliance in the latest snapshot with the same totals as the alliance info top
+// list. Growth costs a query per alliance, so it is only computed when asked for.
+pub async fn get_alliances(
+    pool: &PgPool,
+    server_id: i32,
+    sort: AllianceSort,
+    page: Pagination,
+    include_growth: bool,
+    include_natars: bool,
+) -> Result<AlliancePage> {
+    let _timer = QueryTimer::start("alliances");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    let Some(&(latest_date, _)) = available_dates.first() else {
+        return Ok(AlliancePage { alliances: Vec::new(), total: 0 });
+    };
+    let latest_table = get_table_name_for_server_and_date(server_id, latest_date);
+
+    let grouped = alliance_aggregate_query(&latest_table, alliance_filter(include_natars));
+
+    let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM ({}) alliances", grouped))
+        .bind(server_id)
+        .fetch_one(pool)
+        .await?;
+
+    let query = format!("{}{} LIMIT $2 OFFSET $3", grouped, sort.order_by_clause());
+    let rows = sqlx::query(&query)
+        .bind(server_id)
+        .bind(page.limit)
+        .bind(page.offset)
+        .fetch_all(pool)
+        .await?;
+
+    let previous_table = if include_growth {
+        previous_snapshot_table(pool, server_id, &available_dates).await?
     } else {
         None
     };
+    let server_base_url = get_server(pool, server_id).await?.map(|server| server_base_url(&server.url));
+    let mut alliances = alliance_stats_from_rows(
+        pool,
+        server_id,
+        &rows,
+        previous_table.as_deref(),
+        server_base_url.as_deref(),
+    ).await?;
+
+    if !include_growth {
+        for alliance in &mut alliances {
+            alliance.population_growth = None;
+            alliance.growth_percentage = None;
+        }
+    }
+
+    Ok(AlliancePage { alliances, total })
+}
+
+// Per-alliance totals from one snapshot table; `filter` picks the alliances and $1 is
+// the server id. Yields the columns alliance_stats_from_rows expects.
+fn alliance_aggregate_query(table_name: &str, filter: &str) -> String {
+    format!(
+        "SELECT alliance, aid, COUNT(DISTINCT uid) as member_count, COUNT(*) as village_count, SUM(population) as total_population
+         FROM {} 
+         WHERE server_id = $1 AND {}
+         GROUP BY alliance, aid",
+        table_name, filter
+    )
+}
+
+// The snapshot before the latest one, if it is still there
+async fn previous_snapshot_table(
+    pool: &PgPool,
+    server_id: i32,
+    available_dates: &[(chrono::NaiveDate, i32)],
+) -> Result<Option<String>> {
+    let Some(&(previous_date, _)) = available_dates.get(1) else {
+        return Ok(None);
+    };
+
+    let previous_table = get_table_name_for_server_and_date(server_id, previous_date);
+    Ok(table_exists(pool, &previous_table).await?.then_some(previous_table))
+}
+
+// Turns rows from alliance_aggregate_query into AllianceStats, with growth measured
+// against the same alliance's population in `previous_table`
+async fn alliance_stats_from_rows(
+    pool: &PgPool,
+    server_id: i32,
+    rows: &[PgRow],
+    previous_table: Option<&str>,
+    server_base_url: Option<&str>,
+) -> Result<Vec<AllianceStats>> {
+    let mut alliance_stats = Vec::with_capacity(rows.len());
     
-    for row in alliance_rows {
+    for row in rows {
         let alliance_name: String = row.get("alliance");
         let alliance_id: Option<i32> = row.get("aid");
         let member_count: i64 = row.get("member_count");
@@ -1177,50 +5276,34 @@ pub async fn get_alliance_info_for_server(pool: &PgPool, server_id: i32) -> Resu
         let current_population: i64 = row.get::<Option<i64>, _>("total_population").unwrap_or(0);
         
         // Calculate growth if previous data is available
-        let (population_growth, growth_percentage) = if let Some(ref prev_table) = previous_table {
-            // Check if previous table exists
-            let prev_table_exists: bool = sqlx::query_scalar(
-                "SELECT EXISTS (SELECT FROM information_schema.tables WHERE table_schema = 'public' AND table_name = $1)"
-            )
-            .bind(prev_table)
-            .fetch_one(pool)
-            .await?;
+        let (population_growth, growth_percentage) = if let Some(prev_table) = previous_table {
+            let prev_query = format!(
+                "SELECT SUM(population) as prev_population
+                 FROM {} 
+                 WHERE server_id = $1 AND alliance = $2",
+                prev_table
+            );
             
-            if prev_table_exists {
-                let prev_query = format!(
-                    "SELECT SUM(population) as prev_population
-                     FROM {} 
-                     WHERE server_id = $1 AND alliance = $2",
-                    prev_table
-                );
-                
-                let prev_population: i64 = sqlx::query_scalar(&prev_query)
-                    .bind(server_id)
-                    .bind(&alliance_name)
-                    .fetch_optional(pool)
-                    .await?
-                    .unwrap_or(0);
-                
-                let growth = current_population - prev_population;
-                let growth_pct = if prev_population > 0 {
-                    (growth as f64 / prev_population as f64) * 100.0
-                } else {
-                    0.0
-                };
-                
-                (growth, growth_pct)
+            let prev_population: i64 = sqlx::query_scalar::<_, Option<i64>>(&prev_query)
+                .bind(server_id)
+                .bind(&alliance_name)
+                .fetch_one(pool)
+                .await?
+                .unwrap_or(0);
+            
+            let growth = current_population - prev_population;
+            let growth_pct = if prev_population > 0 {
+                (growth as f64 / prev_population as f64) * 100.0
             } else {
-                (0, 0.0)
-            }
+                0.0
+            };
+            
+            (growth, growth_pct)
         } else {
             (0, 0.0)
         };
         
-        let alliance_link = if let (Some(base_url), Some(aid)) = (&server_base_url, alliance_id) {
-            Some(format!("{}/alliance/{}", base_url, aid))
-        } else {
-            None
-        };
+        let alliance_link = alliance_link(server_base_url, alliance_id);
         
         let avg_pop_per_village = if village_count > 0 {
             (current_population / village_count) as i32
@@ -1235,27 +5318,884 @@ pub async fn get_alliance_info_for_server(pool: &PgPool, server_id: i32) -> Resu
             village_count: village_count as i32,
             total_population: current_population,
             average_population_per_village: avg_pop_per_village,
-            population_growth,
-            growth_percentage,
+            population_growth: Some(population_growth),
+            growth_percentage: Some(growth_percentage),
             alliance_link,
         });
     }
     
-    // Get total number of alliances
-    let total_query = format!(
-        "SELECT COUNT(DISTINCT alliance) as total_alliances
-         FROM {} 
-         WHERE server_id = $1 AND alliance IS NOT NULL AND alliance != '' AND alliance != 'Natars'",
-        latest_table
+    Ok(alliance_stats)
+}
+
+#[derive(Serialize)]
+pub struct AllianceComparison {
+    pub a: AllianceStats,
+    pub b: AllianceStats,
+    pub border_radius: i32,
+    // Villages of either alliance within border_radius fields of the other's, largest first
+    pub contested: Vec<MapData>,
+}
+
+// Head-to-head stats for two alliances in a server's latest snapshot. None when either
+// alliance has no villages there.
+pub async fn compare_alliances(
+    pool: &PgPool,
+    server_id: i32,
+    alliance_a: &str,
+    alliance_b: &str,
+    border_radius: i32,
+    default_map_size: i32,
+) -> Result<Option<AllianceComparison>> {
+    let _timer = QueryTimer::start("compare_alliances");
+    let available_dates = get_available_dates_for_server(pool, server_id).await?;
+    let Some(&(latest_date, _)) = available_dates.first() else {
+        return Ok(None);
+    };
+    let latest_table = get_table_name_for_server_and_date(server_id, latest_date);
+
+    let alliance_query = alliance_aggregate_query(&latest_table, "alliance IN ($2, $3) AND alliance != ''");
+    let alliance_rows = sqlx::query(&alliance_query)
+        .bind(server_id)
+        .bind(alliance_a)
+        .bind(alliance_b)
+        .fetch_all(pool)
+        .await?;
+
+    let server_base_url = get_server(pool, server_id).await?.map(|server| server_base_url(&server.url));
+    let previous_table = previous_snapshot_table(pool, server_id, &available_dates).await?;
+    let mut alliance_stats = alliance_stats_from_rows(
+        pool,
+        server_id,
+        &alliance_rows,
+        previous_table.as_deref(),
+        server_base_url.as_deref(),
+    ).await?;
+
+    // An alliance whose villages carry more than one aid is reported under the larger one
+    alliance_stats.sort_by_key(|stats| std::cmp::Reverse(stats.village_count));
+    let mut take = |name: &str| {
+        alliance_stats
+            .iter()
+            .position(|stats| stats.alliance_name == name)
+            .map(|index| alliance_stats.remove(index))
+    };
+    let (Some(a), Some(b)) = (take(alliance_a), take(alliance_b)) else {
+        return Ok(None);
+    };
+
+    let map_size = get_map_size_for_server(pool, server_id, default_map_size).await?;
+    let contested_query = format!(
+        "SELECT id, village, x, y, population, player, alliance, worldid, tid FROM (
+             SELECT DISTINCT ON (v.id) v.*
+             FROM {table} v
+             JOIN {table} o ON o.server_id = v.server_id
+             WHERE v.server_id = $1
+             AND ((v.alliance = $2 AND o.alliance = $3) OR (v.alliance = $3 AND o.alliance = $2))
+             AND {distance} <= $4::BIGINT * $4::BIGINT
+         ) contested
+         ORDER BY population DESC, id",
+        table = latest_table,
+        distance = toroidal_distance_squared_between_sql(("v.x", "v.y"), ("o.x", "o.y"), "$5"),
     );
-    
-    let total_alliances: i64 = sqlx::query_scalar(&total_query)
+
+    let contested = sqlx::query(&contested_query)
         .bind(server_id)
-        .fetch_one(pool)
+        .bind(alliance_a)
+        .bind(alliance_b)
+        .bind(border_radius)
+        .bind(map_size)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(MapData::from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(AllianceComparison { a, b, border_radius, contested }))
+}
+
+// Drills into one alliance of the active server's latest snapshot: its members, largest
+// first, with the same per-alliance totals get_alliance_info_for_server reports.
+// None when no village belongs to the alliance.
+pub async fn get_alliance_detail(pool: &PgPool, alliance_name: &str) -> Result<Option<AllianceDetail>> {
+    let _timer = QueryTimer::start("alliance_detail");
+    let server = get_active_server(pool).await?.ok_or(NoActiveServer)?;
+
+    let Some(table_name) = get_latest_table_for_server(pool, server.id).await? else {
+        return Ok(None);
+    };
+
+    let member_query = format!(
+        "SELECT player, uid, aid, COUNT(*) as village_count, SUM(population) as total_population
+         FROM {}
+         WHERE server_id = $1 AND alliance = $2 AND alliance != ''
+         GROUP BY player, uid, aid
+         ORDER BY total_population DESC, player",
+        table_name
+    );
+
+    let member_rows = sqlx::query(&member_query)
+        .bind(server.id)
+        .bind(alliance_name)
+        .fetch_all(pool)
         .await?;
-    
-    Ok(AllianceInfo {
-        top_alliances: alliance_stats,
-        total_alliances: total_alliances as i32,
-    })
+
+    if member_rows.is_empty() {
+        return Ok(None);
+    }
+
+    let base_url = server_base_url(&server.url);
+    let alliance_id: Option<i32> = member_rows[0].get("aid");
+
+    let members: Vec<AllianceMember> = member_rows
+        .iter()
+        .map(|row| AllianceMember {
+            player_name: row.get("player"),
+            village_count: row.get::<i64, _>("village_count") as i32,
+            total_population: row.get::<Option<i64>, _>("total_population").unwrap_or(0),
+            profile_link: profile_link(Some(&base_url), row.get("uid")),
+        })
+        .collect();
+
+    Ok(Some(AllianceDetail {
+        alliance_name: alliance_name.to_string(),
+        alliance_id,
+        member_count: members.len() as i32,
+        village_count: members.iter().map(|member| member.village_count).sum(),
+        total_population: members.iter().map(|member| member.total_population).sum(),
+        alliance_link: alliance_link(Some(&base_url), alliance_id),
+        members,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_tuple_of_a_multi_row_insert() {
+        let dump = "INSERT INTO `x_world` VALUES (1,-200,200,1,10,'A',1,'p1',0,'',100),(2,-199,200,2,11,'B, (b)',2,'p2',0,'',200),(3,-198,200,3,12,'C',3,'p3',0,'',300);";
+        let (villages, stats) = parse_x_world_dump(dump, 1);
+
+        assert_eq!(villages.iter().map(|v| (v.x, v.population)).collect::<Vec<_>>(), vec![(-200, 100), (-199, 200), (-198, 300)]);
+        assert_eq!(villages[1].village, "B, (b)");
+        assert_eq!(stats.failed, 0);
+    }
+
+    #[test]
+    fn values_keyword_is_case_insensitive() {
+        for dump in [
+            "INSERT INTO x_world VALUES (1,5,6,1,10,'A',1,'p1',0,'',100);",
+            "insert into x_world values (1,5,6,1,10,'A',1,'p1',0,'',100);",
+        ] {
+            let (villages, stats) = parse_x_world_dump(dump, 1);
+            assert_eq!(villages.len(), 1, "{dump}");
+            assert_eq!((villages[0].x, villages[0].y), (5, 6));
+            assert_eq!(stats.failed, 0);
+        }
+    }
+
+    #[test]
+    fn malformed_tuple_only_skips_itself() {
+        let dump = "INSERT INTO x_world VALUES (1,5,6,1,10,'A',1,'p1',0,'',100),(2,7,8),(3,9,10,1,12,'C',3,'p3',0,'',300);";
+        let (villages, stats) = parse_x_world_dump(dump, 1);
+
+        assert_eq!(villages.len(), 2);
+        assert_eq!(stats.failed, 1);
+    }
+
+    #[test]
+    fn splits_tuples_ignoring_quoted_parentheses() {
+        let tuples = split_values_tuples(" (1,'a)b'),(2,'it''s (x)'), (3,'c\\')'),(4,");
+        assert_eq!(tuples, vec!["1,'a)b'", "2,'it''s (x)'", "3,'c\\')'"]);
+    }
+
+    fn field_values(tuple: &str) -> Vec<String> {
+        split_x_world_fields(tuple).into_iter().map(|field| field.value).collect()
+    }
+
+    #[test]
+    fn doubled_quotes_stay_inside_the_field() {
+        assert_eq!(field_values("1,'Team ''A''',2"), vec!["1", "Team 'A'", "2"]);
+        assert_eq!(field_values("'''',''"), vec!["'", ""]);
+    }
+
+    #[test]
+    fn backslash_escaped_quotes_stay_inside_the_field() {
+        assert_eq!(field_values(r"1,'L\'Alliance',2"), vec!["1", "L'Alliance", "2"]);
+        assert_eq!(field_values(r#"'say \"hi\"','a\\'"#), vec![r#"say "hi""#, r"a\"]);
+    }
+
+    #[test]
+    fn commas_inside_quotes_do_not_split_fields() {
+        assert_eq!(field_values("1,'Rome, Paris, Berlin',\"x,y\", NULL"), vec!["1", "Rome, Paris, Berlin", "x,y", "NULL"]);
+
+        let fields = split_x_world_fields("'',NULL");
+        assert!(fields[0].quoted && fields[0].as_text() == Some(String::new()));
+        assert!(!fields[1].quoted && fields[1].as_text().is_none());
+    }
+
+    #[test]
+    fn repeated_coordinate_keeps_the_last_row() {
+        let dump = "\
+INSERT INTO x_world VALUES (1,5,6,1,10,'Old',1,'p1',0,'',100);
+INSERT INTO x_world VALUES (2,7,8,1,11,'Other',2,'p2',0,'',50);
+INSERT INTO x_world VALUES (1,5,6,1,12,'Resettled',3,'p3',0,'',250),(9,5,6,1,13,'Newest',4,'p4',0,'',300);";
+        let (villages, stats) = parse_x_world_dump(dump, 1);
+
+        assert_eq!(stats.duplicates, 2);
+        assert_eq!(villages.len(), 2);
+        // The surviving row takes the slot of the first one at that coordinate
+        assert_eq!((villages[0].x, villages[0].y), (5, 6));
+        assert_eq!(villages[0].village, "Newest");
+        assert_eq!(villages[0].population, 300);
+        assert_eq!(villages[1].village, "Other");
+    }
+
+    #[test]
+    fn extended_layout_fixture() {
+        let (villages, stats) = parse_x_world_dump(include_str!("../fixtures/x_world_extended.sql"), 1);
+
+        assert_eq!(stats.layout, Some("extended"));
+        assert_eq!(villages.iter().map(|v| v.population).collect::<Vec<_>>(), vec![498, 812, 1045]);
+        assert_eq!(villages[1].capital.as_deref(), Some("TRUE"));
+        assert_eq!(villages[1].alliance.as_deref(), Some("Bären"));
+        assert!(villages[2].is_ww);
+        assert_eq!(villages[2].ww_name.as_deref(), Some("Wonder of Bären"));
+    }
+
+    #[test]
+    fn legends_layout_fixture() {
+        let (villages, stats) = parse_x_world_dump(include_str!("../fixtures/x_world_legends.sql"), 1);
+
+        assert_eq!(stats.layout, Some("legends"));
+        assert_eq!(villages.iter().map(|v| v.population).collect::<Vec<_>>(), vec![812, 1045, 156]);
+        // isCapital sits after the region name, not where the extended layout keeps capital
+        assert_eq!(villages.iter().map(|v| v.capital.as_deref()).collect::<Vec<_>>(), vec![Some("TRUE"), Some("FALSE"), Some("TRUE")]);
+        assert!(villages.iter().all(|v| !v.is_ww && v.ww_name.is_none()));
+    }
+
+    #[test]
+    fn unknown_layout_falls_back_to_classic_fields() {
+        let (villages, stats) = parse_x_world_dump("INSERT INTO x_world VALUES (1,5,6,1,10,'A',1,'p1',0,'',100,'x','y');", 1);
+
+        assert_eq!(stats.layout, Some("classic"));
+        assert_eq!(villages[0].population, 100);
+        assert!(villages[0].capital.is_none());
+    }
+
+    #[test]
+    fn gzipped_dump_decodes_and_parses() {
+        use std::io::Write;
+
+        let dump = "INSERT INTO x_world VALUES (1,5,6,1,10,'A',1,'p1',0,'',100),(2,7,8,1,11,'B',2,'p2',0,'',200);\n";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(dump.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let sql_content = decode_sql_bytes(&decompress_gzip(&gzipped).unwrap());
+        assert_eq!(sql_content, dump);
+        assert_eq!(parse_x_world_dump(&sql_content, 1).0.len(), 2);
+    }
+
+    #[test]
+    fn plain_bytes_are_not_mistaken_for_gzip() {
+        assert!(decompress_gzip(b"INSERT INTO x_world VALUES (1);").is_err());
+    }
+
+    // Schema plus a new server, made the active one, for tests that need a real database.
+    // #[sqlx::test] gives every test its own throwaway database, created through
+    // DATABASE_URL. The first server gets id 1, which create_tables has already given an
+    // empty snapshot for today.
+    async fn create_test_server(pool: &PgPool, name: &str) -> i32 {
+        create_tables(pool).await.unwrap();
+        let server_id = sqlx::query_scalar("INSERT INTO servers (name, url) VALUES ($1, 'http://travian.invalid') RETURNING id")
+            .bind(name)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        set_active_server(pool, server_id).await.unwrap();
+        server_id
+    }
+
+    #[test]
+    fn umlauts_survive_decoding_and_parsing() {
+        let dump = "\u{feff}INSERT INTO x_world VALUES (1,5,6,1,10,'Dörfchen′',1,'Jürgen',7,'Bären Ü',100);";
+        let (villages, _) = parse_x_world_dump(&decode_sql_bytes(dump.as_bytes()), 1);
+
+        assert_eq!(villages[0].village, "Dörfchen");
+        assert_eq!(villages[0].player.as_deref(), Some("Jürgen"));
+        assert_eq!(villages[0].alliance.as_deref(), Some("Bären Ü"));
+        assert_eq!(clean_name("Natars 173|146â€²"), "Natars 173|146");
+    }
+
+    #[sqlx::test]
+    async fn umlauts_round_trip_into_the_database(pool: PgPool) {
+        let server_id = create_test_server(&pool, "umlauts").await;
+        let dump = "INSERT INTO x_world VALUES (1,5,6,1,10,'Dörfchen',1,'Jürgen',7,'Bären Ü',100);";
+        execute_sql_for_server(&pool, &decode_sql_bytes(dump.as_bytes()), server_id, "test", None).await.unwrap();
+
+        let villages = get_villages_for_server(&pool, server_id, true).await.unwrap();
+        assert_eq!(villages.len(), 1);
+        assert_eq!(villages[0].name, "Dörfchen");
+        assert_eq!(villages[0].player.as_deref(), Some("Jürgen"));
+        assert_eq!(villages[0].alliance.as_deref(), Some("Bären Ü"));
+    }
+
+    // Villages as (x, y, tid, player, alliance, population)
+    type TestVillage<'a> = (i32, i32, i32, &'a str, &'a str, i32);
+
+    fn x_world_dump(villages: &[TestVillage]) -> String {
+        villages
+            .iter()
+            .enumerate()
+            .map(|(index, (x, y, tid, player, alliance, population))| {
+                format!(
+                    "INSERT INTO `x_world` VALUES ({},{},{},{},{},'{} {}|{}',{},'{}',{},'{}',{});\n",
+                    index + 1, x, y, tid, index + 100, player, x, y, player.len(), player, alliance.len(), alliance, population
+                )
+            })
+            .collect()
+    }
+
+    async fn load_test_villages(pool: &PgPool, server_id: i32, villages: &[TestVillage<'_>]) {
+        execute_sql_for_server(pool, &x_world_dump(villages), server_id, "test", None).await.unwrap();
+    }
+
+    fn coordinates(villages: &[MapData]) -> Vec<(i32, i32)> {
+        let mut coordinates: Vec<_> = villages.iter().map(|village| (village.x, village.y)).collect();
+        coordinates.sort();
+        coordinates
+    }
+
+    #[sqlx::test]
+    async fn radius_includes_the_boundary_and_nothing_past_it(pool: PgPool) {
+        let server_id = create_test_server(&pool, "radius").await;
+        load_test_villages(&pool, server_id, &[
+            (5, 0, 1, "a", "", 10),
+            (6, 0, 1, "b", "", 10),
+            (3, 4, 1, "c", "", 10),
+            (4, 4, 1, "d", "", 10),
+            (0, -5, 1, "e", "", 10),
+        ]).await;
+
+        let near = get_villages_near(&pool, 0, 0, 5, Some(401), true, DEFAULT_MAP_SIZE).await.unwrap();
+        assert_eq!(coordinates(&near), vec![(0, -5), (3, 4), (5, 0)]);
+    }
+
+    #[sqlx::test]
+    async fn radius_wraps_around_the_map_edge(pool: PgPool) {
+        let server_id = create_test_server(&pool, "wrap").await;
+        load_test_villages(&pool, server_id, &[
+            (-200, 0, 1, "a", "", 10),
+            (-198, 0, 1, "b", "", 10),
+            (-197, 0, 1, "c", "", 10),
+            (198, -200, 1, "d", "", 10),
+            (190, 0, 1, "e", "", 10),
+        ]).await;
+
+        let near = get_villages_near(&pool, 198, 200, 5, Some(401), true, DEFAULT_MAP_SIZE).await.unwrap();
+        assert_eq!(coordinates(&near), vec![(198, -200)]);
+
+        let near = get_villages_near(&pool, 198, 0, 5, Some(401), true, DEFAULT_MAP_SIZE).await.unwrap();
+        assert_eq!(coordinates(&near), vec![(-200, 0), (-198, 0)]);
+    }
+
+    fn first_page() -> Pagination {
+        Pagination { limit: 100, offset: 0 }
+    }
+
+    #[sqlx::test]
+    async fn player_filter_returns_their_villages_by_population(pool: PgPool) {
+        let server_id = create_test_server(&pool, "players").await;
+        load_test_villages(&pool, server_id, &[
+            (1, 1, 1, "Kuno", "A", 100),
+            (2, 2, 1, "Kuno", "A", 300),
+            (3, 3, 1, "Edda", "A", 500),
+            (4, 4, 1, "kuno", "A", 700),
+        ]).await;
+
+        let filter = VillageFilter { player: Some("Kuno".to_string()), ..Default::default() };
+        let page = get_filtered_villages(&pool, &filter, None, first_page()).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.villages.iter().map(|v| v.population).collect::<Vec<_>>(), vec![300, 100]);
+
+        let filter = VillageFilter { player: Some("Nobody".to_string()), ..Default::default() };
+        let page = get_filtered_villages(&pool, &filter, None, first_page()).await.unwrap();
+        assert_eq!(page.total, 0);
+        assert!(page.villages.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn alliance_filter_skips_natars_and_unaffiliated_villages(pool: PgPool) {
+        let server_id = create_test_server(&pool, "alliances").await;
+        load_test_villages(&pool, server_id, &[
+            (1, 1, 1, "Kuno", "Bären", 100),
+            (2, 2, 2, "Edda", "Bären", 300),
+            (3, 3, 5, "Natars", "", 900),
+            (4, 4, 1, "Solo", "", 50),
+            (5, 5, 1, "Other", "Wölfe", 400),
+        ]).await;
+
+        let filter = VillageFilter { alliance: Some("Bären".to_string()), include_nature: true, ..Default::default() };
+        let page = get_filtered_villages(&pool, &filter, None, first_page()).await.unwrap();
+        assert_eq!(page.villages.iter().map(|v| v.player.as_deref().unwrap()).collect::<Vec<_>>(), vec!["Edda", "Kuno"]);
+
+        let filter = VillageFilter { alliance: Some(String::new()), include_nature: true, ..Default::default() };
+        let page = get_filtered_villages(&pool, &filter, None, first_page()).await.unwrap();
+        assert_eq!(page.total, 0);
+    }
+
+    #[sqlx::test]
+    async fn include_natars_changes_player_and_alliance_counts(pool: PgPool) {
+        let server_id = create_test_server(&pool, "natars").await;
+        load_test_villages(&pool, server_id, &[
+            (1, 1, 1, "Kuno", "Bären", 100),
+            (2, 2, 2, "Edda", "Bären", 300),
+            (3, 3, 5, "Natars", "Natars", 900),
+            (4, 4, 5, "Natars", "Natars", 800),
+            (5, 5, 1, "Solo", "", 50),
+        ]).await;
+
+        let world_info = |include_natars| get_world_info_for_server(&pool, server_id, include_natars);
+        let top_players = |info: WorldInfo| info.top_players.into_iter().map(|player| player.player_name).collect::<Vec<_>>();
+        assert_eq!(top_players(world_info(false).await.unwrap()), vec!["Edda", "Kuno", "Solo"]);
+        assert_eq!(top_players(world_info(true).await.unwrap()), vec!["Natars", "Edda", "Kuno", "Solo"]);
+
+        let players = |include_natars| get_players(&pool, server_id, PlayerSort::Population, first_page(), include_natars);
+        assert_eq!(players(false).await.unwrap().total, 3);
+        assert_eq!(players(true).await.unwrap().total, 4);
+
+        let alliances = |include_natars| get_alliances(&pool, server_id, AllianceSort::Population, first_page(), false, include_natars);
+        assert_eq!(alliances(false).await.unwrap().total, 1);
+        assert_eq!(alliances(true).await.unwrap().total, 2);
+        assert_eq!(get_alliance_info_for_server(&pool, server_id, false).await.unwrap().total_alliances, 1);
+        assert_eq!(get_alliance_info_for_server(&pool, server_id, true).await.unwrap().total_alliances, 2);
+
+        let counts = |stats: SummaryStats| (stats.player_count, stats.alliance_count, stats.tribe_count);
+        assert_eq!(counts(get_summary_stats(&pool, server_id, false).await.unwrap()), (3, 1, 2));
+        assert_eq!(counts(get_summary_stats(&pool, server_id, true).await.unwrap()), (4, 2, 3));
+    }
+
+    #[sqlx::test]
+    async fn pages_stop_at_the_last_village(pool: PgPool) {
+        let server_id = create_test_server(&pool, "pages").await;
+        let villages: Vec<TestVillage> = (1..=5).map(|i| (i, i, 1, "p", "", i * 10)).collect();
+        load_test_villages(&pool, server_id, &villages).await;
+
+        let filter = VillageFilter::default();
+        let page = |limit, offset| {
+            let filter = &filter;
+            let pool = &pool;
+            async move {
+                let page = get_filtered_villages(pool, filter, None, Pagination { limit, offset }).await.unwrap();
+                (page.total, page.villages.iter().map(|v| v.population).collect::<Vec<_>>())
+            }
+        };
+
+        assert_eq!(page(2, 0).await, (5, vec![50, 40]));
+        assert_eq!(page(2, 2).await, (5, vec![30, 20]));
+        assert_eq!(page(2, 4).await, (5, vec![10]));
+        assert_eq!(page(2, 5).await, (5, vec![]));
+        assert_eq!(page(10, 0).await.1.len(), 5);
+    }
+
+    // A snapshot for an earlier day, recorded in load_history the way a real load is
+    async fn load_test_snapshot(pool: &PgPool, server_id: i32, date: chrono::NaiveDate, villages: &[TestVillage<'_>]) {
+        let table_name = create_table_for_server_and_date(pool, server_id, date).await.unwrap();
+        let (parsed, _) = parse_x_world_dump(&x_world_dump(villages), server_id);
+        let mut conn = pool.acquire().await.unwrap();
+        insert_parsed_villages_to_table_with_server(&mut conn, &parsed, &table_name, server_id).await.unwrap();
+        sqlx::query("INSERT INTO load_history (server_id, date, village_count, source_url, status) VALUES ($1, $2, $3, 'test', 'success')")
+            .bind(server_id)
+            .bind(date)
+            .bind(villages.len() as i32)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn days_ago(days: i64) -> chrono::NaiveDate {
+        chrono::Utc::now().date_naive() - chrono::Duration::days(days)
+    }
+
+    #[sqlx::test]
+    async fn new_load_busts_the_world_info_cache(pool: PgPool) {
+        create_test_server(&pool, "default").await;
+        let server_id = create_test_server(&pool, "cache").await;
+        load_test_snapshot(&pool, server_id, days_ago(1), &[(1, 1, 1, "a", "", 10)]).await;
+
+        let cache = WorldInfoCache::new(4);
+        let version = || async { get_active_snapshot_version(&pool, DEFAULT_MAP_SIZE).await.unwrap().unwrap() };
+        assert_eq!(get_world_info(&pool, &cache, false, DEFAULT_MAP_SIZE).await.unwrap().total_villages, 1);
+        assert!(cache.get(server_id, &version().await).is_some());
+
+        load_test_villages(&pool, server_id, &[(1, 1, 1, "a", "", 10), (2, 2, 1, "b", "", 20)]).await;
+        assert_eq!(get_world_info(&pool, &cache, false, DEFAULT_MAP_SIZE).await.unwrap().total_villages, 2);
+        assert!(cache.get(server_id, &version().await).is_some());
+
+        // A same-day edit changes the version, so nothing has to invalidate the cache
+        add_village(&pool, &NewVillage { name: "Outpost", x: -1, y: 0, population: 30 }, DEFAULT_MAP_SIZE).await.unwrap();
+        assert_eq!(get_world_info(&pool, &cache, false, DEFAULT_MAP_SIZE).await.unwrap().total_villages, 3);
+    }
+
+    #[sqlx::test]
+    async fn cleanup_keeps_the_ten_newest_tables_per_server(pool: PgPool) {
+        let default_server = create_test_server(&pool, "default").await;
+        let server_id = create_test_server(&pool, "retention").await;
+        for days in 0..12 {
+            create_table_for_server_and_date(&pool, server_id, days_ago(days)).await.unwrap();
+        }
+
+        cleanup_old_tables(&pool, DEFAULT_TABLE_RETENTION_DAYS).await.unwrap();
+
+        let dates: Vec<_> = get_available_dates_for_server(&pool, server_id).await.unwrap().into_iter().map(|(date, _)| date).collect();
+        assert_eq!(dates, (0..10).map(days_ago).collect::<Vec<_>>());
+        assert_eq!(get_available_dates_for_server(&pool, default_server).await.unwrap().len(), 1);
+
+        // A smaller configured retention applies on the next cleanup
+        cleanup_old_tables(&pool, 3).await.unwrap();
+        assert_eq!(get_available_dates_for_server(&pool, server_id).await.unwrap().len(), 3);
+    }
+
+    #[sqlx::test]
+    async fn created_villages_join_the_latest_snapshot(pool: PgPool) {
+        // Startup creates today's table for the first server, so use a second one
+        create_test_server(&pool, "default").await;
+        let server_id = create_test_server(&pool, "manual").await;
+        load_test_snapshot(&pool, server_id, days_ago(1), &[(1, 1, 1, "a", "", 10), (2, 2, 1, "b", "", 20)]).await;
+
+        add_village(&pool, &NewVillage { name: "Outpost", x: 3, y: 3, population: 30 }, DEFAULT_MAP_SIZE).await.unwrap();
+        add_villages_bulk(&pool, &[NewVillage { name: "Camp", x: 4, y: 4, population: 40 }], DEFAULT_MAP_SIZE).await.unwrap();
+        let imported = ImportedVillage {
+            name: "Fort".to_string(),
+            x: 5,
+            y: 5,
+            population: 50,
+            player: "c".to_string(),
+            alliance: String::new(),
+            worldid: None,
+        };
+        import_villages(&pool, &[imported], DEFAULT_MAP_SIZE).await.unwrap();
+
+        let villages = get_all_villages(&pool, false).await.unwrap();
+        assert_eq!(coordinates(&villages), vec![(1, 1), (2, 2), (3, 3), (4, 4), (5, 5)]);
+        assert_eq!(get_latest_data_date_for_server(&pool, server_id).await.unwrap(), Some(days_ago(1)));
+    }
+
+    #[sqlx::test]
+    async fn created_village_is_listed_without_marking_today_loaded(pool: PgPool) {
+        let server_id = create_test_server(&pool, "manual").await;
+        let village = NewVillage { name: "Outpost", x: 12, y: -34, population: 42 };
+        let created = add_village(&pool, &village, DEFAULT_MAP_SIZE).await.unwrap();
+
+        let villages = get_all_villages(&pool, false).await.unwrap();
+        assert_eq!(villages.len(), 1);
+        assert_eq!((villages[0].id, villages[0].name.as_str(), villages[0].x, villages[0].y), (created.id, "Outpost", 12, -34));
+
+        assert!(is_new_data_needed_for_server(&pool, server_id).await.unwrap());
+        load_test_villages(&pool, server_id, &[(1, 1, 1, "a", "", 10)]).await;
+        assert!(!is_new_data_needed_for_server(&pool, server_id).await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn village_can_be_created_updated_and_deleted(pool: PgPool) {
+        create_test_server(&pool, "crud").await;
+        let created = add_village(&pool, &NewVillage { name: "Outpost", x: 1, y: 2, population: 42 }, DEFAULT_MAP_SIZE).await.unwrap();
+
+        let update = VillageUpdate { name: Some("Fortress"), population: Some(99), player: None, alliance: None };
+        let updated = update_village(&pool, created.id, &update).await.unwrap().unwrap();
+        assert_eq!((updated.name.as_str(), updated.population), ("Fortress", 99));
+        assert_eq!(get_all_villages(&pool, false).await.unwrap()[0].name, "Fortress");
+
+        assert!(update_village(&pool, created.id + 1, &update).await.unwrap().is_none());
+        assert!(!delete_village(&pool, created.id + 1).await.unwrap());
+        assert!(delete_village(&pool, created.id).await.unwrap());
+        assert!(get_all_villages(&pool, false).await.unwrap().is_empty());
+        assert!(!delete_village(&pool, created.id).await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn villages_fit_up_to_the_map_edge(pool: PgPool) {
+        use axum::response::IntoResponse;
+
+        let server_id = create_test_server(&pool, "edges").await;
+        // Without villages there is no detected size yet, so the configured MAP_SIZE applies
+        assert!(get_server(&pool, server_id).await.unwrap().unwrap().map_size.is_none());
+        let map_size = 21;
+        let half = map_size / 2;
+        let village = |x, y| NewVillage { name: "Edge", x, y, population: 1 };
+
+        for (x, y) in [(-half, -half), (half, half), (-half, half), (half, -half)] {
+            add_village(&pool, &village(x, y), map_size).await.unwrap();
+        }
+
+        for (x, y) in [(-half - 1, 0), (half + 1, 0), (0, -half - 1), (0, half + 1)] {
+            let Err(error) = add_village(&pool, &village(x, y), map_size).await else {
+                panic!("({x}|{y}) was accepted on a map of size {map_size}");
+            };
+            assert!(error.is::<CoordinateOutOfRange>());
+            let response = crate::error::ApiError::from(error).into_response();
+            assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        }
+
+        // One village past the edge rejects the whole batch
+        let Err(error) = add_villages_bulk(&pool, &[village(0, half), village(0, half + 1)], map_size).await else {
+            panic!("a batch reaching past the edge was accepted");
+        };
+        assert!(error.is::<CoordinateOutOfRange>());
+        add_villages_bulk(&pool, &[village(1, half), village(1, -half)], map_size).await.unwrap();
+        assert_eq!(get_all_villages(&pool, false).await.unwrap().len(), 6);
+    }
+
+    // Serves `dump` as map.sql on a local port after `delay`, returning the server's URL
+    async fn serve_dump(dump: String, delay: Duration) -> String {
+        let app = axum::Router::new().route(
+            "/map.sql",
+            axum::routing::get(move || async move {
+                tokio::time::sleep(delay).await;
+                dump
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", address)
+    }
+
+    async fn point_server_at(pool: &PgPool, server_id: i32, url: &str) -> Server {
+        sqlx::query("UPDATE servers SET url = $1 WHERE id = $2").bind(url).bind(server_id).execute(pool).await.unwrap();
+        get_server(pool, server_id).await.unwrap().unwrap()
+    }
+
+    #[sqlx::test]
+    async fn concurrent_loads_of_one_server_do_not_overlap(pool: PgPool) {
+        let server_id = create_test_server(&pool, "locked").await;
+        let villages: Vec<TestVillage> = (1..=50).map(|i| (i, -i, 1, "p", "", i)).collect();
+        let url = serve_dump(x_world_dump(&villages), Duration::from_millis(500)).await;
+        let server = point_server_at(&pool, server_id, &url).await;
+
+        let locks = std::sync::Arc::new(ServerLoadLocks::new());
+        let fetcher = std::sync::Arc::new(MapFetcher::new(Duration::from_secs(5)).unwrap());
+        let settings = LoadSettings { webhook: None, table_retention_days: DEFAULT_TABLE_RETENTION_DAYS };
+        let load = || {
+            let (pool, locks, fetcher, settings, server) =
+                (pool.clone(), locks.clone(), fetcher.clone(), settings.clone(), server.clone());
+            tokio::spawn(async move { auto_load_data_for_server(&pool, &locks, &fetcher, &settings, &server).await })
+        };
+
+        let first = load();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let second = load().await.unwrap();
+        assert!(second.is_err_and(|e| e.is::<crate::load_lock::LoadInProgress>()));
+
+        let first = first.await.unwrap().unwrap();
+        assert_eq!(first.stats.unwrap().inserted, 50);
+
+        // Once the first load is done the lock is free again, and today's data is in
+        let third = load().await.unwrap().unwrap();
+        assert!(third.stats.is_none());
+
+        let table_name = get_latest_table_for_server(&pool, server_id).await.unwrap().unwrap();
+        let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table_name)).fetch_one(&pool).await.unwrap();
+        assert_eq!(rows, 50);
+    }
+
+    #[sqlx::test]
+    async fn reload_jobs_notify_the_webhook(pool: PgPool) {
+        let server_id = create_test_server(&pool, "announced").await;
+        let villages: Vec<TestVillage> = (1..=3).map(|i| (i, i, 1, "p", "", i)).collect();
+        let url = serve_dump(x_world_dump(&villages), Duration::ZERO).await;
+        let server = point_server_at(&pool, server_id, &url).await;
+
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let app = axum::Router::new().route(
+            "/hook",
+            axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                sender.send(body).unwrap();
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let webhook = Arc::new(Webhook::new(hook_url, crate::webhook::WebhookFormat::Json).unwrap());
+        let jobs = Arc::new(crate::jobs::LoadJobs::new(LoadSettings {
+            webhook: Some(webhook),
+            table_retention_days: DEFAULT_TABLE_RETENTION_DAYS,
+        }));
+        let locks = ServerLoadLocks::new();
+        let fetcher = Arc::new(MapFetcher::new(Duration::from_secs(5)).unwrap());
+        let cache = Arc::new(WorldInfoCache::new(1));
+
+        let guard = locks.try_lock(server_id).unwrap();
+        crate::jobs::spawn_reload(jobs, pool, fetcher, cache, server, guard);
+
+        let body = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+        assert_eq!(body["event"], "load");
+        assert_eq!(body["server_id"], server_id);
+        assert_eq!(body["village_count"], 3);
+    }
+
+    #[sqlx::test]
+    async fn old_layout_table_keeps_the_newest_row_per_tile(pool: PgPool) {
+        let server_id = create_test_server(&pool, "upgrade").await;
+        let date = days_ago(3);
+        let table_name = get_table_name_for_server_and_date(server_id, date);
+
+        // The layout before upserts: no first_seen, a plain index, duplicate tiles
+        sqlx::query(&format!(
+            "CREATE TABLE {table} (id SERIAL PRIMARY KEY, server_id INTEGER NOT NULL, worldid INTEGER, x INTEGER NOT NULL, y INTEGER NOT NULL, \
+             tid INTEGER, vid INTEGER, village VARCHAR(255) NOT NULL, uid INTEGER, player VARCHAR(255), aid INTEGER, alliance VARCHAR(255), \
+             population INTEGER NOT NULL DEFAULT 0, capital VARCHAR(10), isWW BOOLEAN DEFAULT FALSE, wwname VARCHAR(255), \
+             created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(), updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW())",
+            table = table_name
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(&format!("CREATE INDEX idx_{table}_coordinates ON {table} (server_id, x, y)", table = table_name))
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(&format!(
+            "INSERT INTO {} (server_id, x, y, village, population, updated_at) VALUES \
+             ($1, 1, 1, 'old', 10, NOW() - INTERVAL '2 hours'), ($1, 1, 1, 'newest', 30, NOW()), ($1, 1, 1, 'older', 20, NOW() - INTERVAL '1 hour'), \
+             ($1, 2, 2, 'alone', 40, NOW())",
+            table_name
+        ))
+        .bind(server_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        create_table_for_server_and_date(&pool, server_id, date).await.unwrap();
+
+        let villages = get_villages_by_server_and_date(&pool, server_id, date, true).await.unwrap();
+        let mut names: Vec<_> = villages.iter().map(|village| village.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["alone", "newest"]);
+
+        let indexes: Vec<String> = sqlx::query_scalar("SELECT indexname::TEXT FROM pg_indexes WHERE tablename = $1")
+            .bind(&table_name)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert!(indexes.contains(&format!("idx_{}_position", table_name)));
+        assert!(!indexes.contains(&format!("idx_{}_coordinates", table_name)));
+
+        // Calling it again on the upgraded table changes nothing
+        create_table_for_server_and_date(&pool, server_id, date).await.unwrap();
+        assert_eq!(get_villages_by_server_and_date(&pool, server_id, date, true).await.unwrap().len(), 2);
+    }
+
+    // Yesterday and today for five players; only Dora grew, so every other village is AFK
+    async fn load_afk_fixture(pool: &PgPool) -> i32 {
+        let server_id = create_test_server(pool, "afk").await;
+        let yesterday: [TestVillage; 6] = [
+            (1, 1, 1, "Anna", "", 100),
+            (2, 2, 1, "Anna", "", 300),
+            (-1, -1, 2, "Bert", "", 200),
+            (3, -3, 3, "Carl", "", 50),
+            (-5, 5, 1, "Dora", "", 400),
+            (-7, 7, 2, "Emil", "", 70),
+        ];
+        let mut today = yesterday;
+        today[4].5 = 450;
+        load_test_snapshot(pool, server_id, days_ago(1), &yesterday).await;
+        load_test_villages(pool, server_id, &today).await;
+        server_id
+    }
+
+    fn afk_search(quadrant: &str) -> AfkSearchParams {
+        AfkSearchParams { quadrant: quadrant.to_string(), days: 1, min_population: None, limit: None, offset: None }
+    }
+
+    fn afk_populations(page: &AfkVillagePage) -> Vec<i32> {
+        page.villages.iter().map(|village| village.population).collect()
+    }
+
+    #[sqlx::test]
+    async fn afk_search_filters_by_population_and_pages(pool: PgPool) {
+        let server_id = load_afk_fixture(&pool).await;
+
+        let page = find_afk_villages_for_server(&pool, server_id, afk_search("ALL")).await.unwrap();
+        assert_eq!((page.total, afk_populations(&page)), (5, vec![300, 200, 100, 70, 50]));
+
+        let search = AfkSearchParams { min_population: Some(100), ..afk_search("ALL") };
+        let page = find_afk_villages_for_server(&pool, server_id, search).await.unwrap();
+        assert_eq!((page.total, afk_populations(&page)), (3, vec![300, 200, 100]));
+
+        let search = AfkSearchParams { limit: Some(2), offset: Some(1), ..afk_search("ALL") };
+        let page = find_afk_villages_for_server(&pool, server_id, search).await.unwrap();
+        assert_eq!((page.total, afk_populations(&page)), (5, vec![200, 100]));
+
+        let search = AfkSearchParams { limit: Some(2), offset: Some(5), ..afk_search("ALL") };
+        let page = find_afk_villages_for_server(&pool, server_id, search).await.unwrap();
+        assert_eq!((page.total, afk_populations(&page)), (5, vec![]));
+    }
+
+    #[sqlx::test]
+    async fn afk_search_across_all_quadrants_is_their_union(pool: PgPool) {
+        let server_id = load_afk_fixture(&pool).await;
+        let coordinates = |page: AfkVillagePage| page.villages.into_iter().map(|village| (village.x, village.y)).collect::<Vec<_>>();
+
+        let mut union = Vec::new();
+        for quadrant in QUADRANTS {
+            let page = find_afk_villages_for_server(&pool, server_id, afk_search(quadrant)).await.unwrap();
+            assert!(!page.villages.is_empty(), "{quadrant}");
+            union.extend(coordinates(page));
+        }
+        union.sort();
+
+        let mut all = coordinates(find_afk_villages_for_server(&pool, server_id, afk_search(ALL_QUADRANTS)).await.unwrap());
+        all.sort();
+        assert_eq!(all, union);
+        assert_eq!(all.len(), 5);
+    }
+
+    fn validation_error(params: AfkSearchParams) -> String {
+        params.validate().unwrap_err().0
+    }
+
+    #[test]
+    fn afk_search_rejects_days_outside_one_to_ten() {
+        assert!(validation_error(AfkSearchParams { days: 0, ..afk_search("NE") }).contains("got 0"));
+        assert!(validation_error(AfkSearchParams { days: 99, ..afk_search("NE") }).contains("got 99"));
+        assert!(AfkSearchParams { days: 10, ..afk_search("NE") }.validate().is_ok());
+    }
+
+    #[test]
+    fn afk_search_rejects_unknown_quadrants() {
+        assert!(validation_error(afk_search("XX")).contains("\"XX\""));
+        assert!(validation_error(afk_search("ne")).contains("\"ne\""));
+        for quadrant in QUADRANTS.iter().chain([&ALL_QUADRANTS]) {
+            assert!(afk_search(quadrant).validate().is_ok(), "{quadrant}");
+        }
+    }
+
+    #[test]
+    fn afk_validation_failures_are_bad_requests() {
+        use axum::response::IntoResponse;
+
+        let error = crate::error::ApiError::from(afk_search("XX").validate().unwrap_err());
+        assert_eq!(error.into_response().status(), axum::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn duplicate_server_name_is_a_conflict(pool: PgPool) {
+        use axum::response::IntoResponse;
+
+        create_test_server(&pool, "default").await;
+        add_server(&pool, "ts1", "http://ts1.travian.invalid").await.unwrap();
+
+        let Err(error) = add_server(&pool, "ts1", "http://other.travian.invalid").await else {
+            panic!("a second ts1 was added");
+        };
+        assert!(error.is::<ServerNameTaken>());
+        let response = crate::error::ApiError::from(error).into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::CONFLICT);
+
+        let servers = get_all_servers(&pool).await.unwrap();
+        assert_eq!(servers.iter().filter(|server| server.name == "ts1").count(), 1);
+    }
 }
@@ -0,0 +1,119 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use crate::database::{
+    AlreadyWatched, CoordinateOccupied, CoordinateOutOfRange, InvalidSearchParams, NoActiveServer, ServerNameTaken, SnapshotNotFound,
+};
+use crate::load_lock::LoadInProgress;
+use std::time::Duration;
+
+// Every handler error is rendered as { "status": "error", "message": ... } so the
+// frontend always has something to show next to the status code.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    NotFound(String),
+    NoActiveServer,
+    Conflict(String),
+    TooManyRequests { retry_after: Duration },
+    // An extractor could not read the request; keeps the status axum chose (400, 415, 422...)
+    Rejected { status: StatusCode, message: String },
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        ApiError::BadRequest(message.into())
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::NotFound(message.into())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        // Typed database errors carry their own status; everything else is a 500
+        if error.is::<NoActiveServer>() {
+            ApiError::NoActiveServer
+        } else if error.is::<InvalidSearchParams>() || error.is::<CoordinateOutOfRange>() {
+            ApiError::BadRequest(error.to_string())
+        } else if error.is::<SnapshotNotFound>() {
+            ApiError::NotFound(error.to_string())
+        } else if error.is::<LoadInProgress>()
+            || error.is::<CoordinateOccupied>()
+            || error.is::<ServerNameTaken>()
+            || error.is::<AlreadyWatched>()
+        {
+            ApiError::Conflict(error.to_string())
+        } else {
+            ApiError::Internal(error)
+        }
+    }
+}
+
+impl From<InvalidSearchParams> for ApiError {
+    fn from(error: InvalidSearchParams) -> Self {
+        ApiError::BadRequest(error.0)
+    }
+}
+
+impl From<LoadInProgress> for ApiError {
+    fn from(error: LoadInProgress) -> Self {
+        ApiError::Conflict(error.to_string())
+    }
+}
+
+macro_rules! impl_from_rejection {
+    ($($rejection:ty),*) => {
+        $(
+            impl From<$rejection> for ApiError {
+                fn from(rejection: $rejection) -> Self {
+                    ApiError::Rejected { status: rejection.status(), message: rejection.body_text() }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_rejection!(JsonRejection, QueryRejection, PathRejection);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        // Retry-After only takes whole seconds, so round up rather than invite an early retry
+        let retry_after = match &self {
+            ApiError::TooManyRequests { retry_after } => Some(retry_after.as_secs_f64().ceil().max(1.0) as u64),
+            _ => None,
+        };
+
+        let (status, message) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::NoActiveServer => (StatusCode::NOT_FOUND, NoActiveServer.to_string()),
+            ApiError::Conflict(message) => (StatusCode::CONFLICT, message),
+            ApiError::TooManyRequests { .. } => (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded".to_string()),
+            ApiError::Rejected { status, message } => (status, message),
+            // The details (often a database error naming tables and columns) stay in the log
+            ApiError::Internal(error) => {
+                tracing::error!("Internal error: {:#}", error);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
+            }
+        };
+
+        let mut response = (status, Json(serde_json::json!({
+            "status": "error",
+            "message": message
+        })))
+            .into_response();
+
+        if let Some(secs) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, secs.into());
+        }
+
+        response
+    }
+}
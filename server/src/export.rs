@@ -0,0 +1,285 @@
+use axum::body::{Body, Bytes};
+use futures_util::{StreamExt, TryStreamExt};
+use crate::database::X_WORLD_COLUMNS;
+use sqlx::{postgres::PgRow, Column, PgPool, Row, TypeInfo};
+use std::future::Future;
+use tokio::sync::mpsc;
+
+// Rows are buffered into chunks of this many before being sent to the client
+const ROWS_PER_CHUNK: usize = 1000;
+
+type Chunk = Result<Bytes, std::io::Error>;
+
+// Exports read rows from a cursor on a background task and forward them through a
+// bounded channel, so a full map never sits in memory at once. An error after the
+// response has started aborts the body, which the client sees as a truncated download.
+fn spawn_export<F, Fut>(format: &'static str, produce: F) -> Body
+where
+    F: FnOnce(mpsc::Sender<Chunk>) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel::<Chunk>(4);
+    let error_sender = sender.clone();
+
+    tokio::spawn(async move {
+        if let Err(e) = produce(sender).await {
+            tracing::error!(format, error = %e, "Export failed");
+            let _ = error_sender.send(Err(std::io::Error::other(e.to_string()))).await;
+        }
+    });
+
+    // Fused because the compression layer may poll once more after the stream has ended
+    let chunks = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    Body::from_stream(chunks.fuse())
+}
+
+// Returns false once the client has gone away
+async fn send_chunk(sender: &mpsc::Sender<Chunk>, chunk: Vec<u8>) -> bool {
+    chunk.is_empty() || sender.send(Ok(Bytes::from(chunk))).await.is_ok()
+}
+
+pub fn villages_csv_body(pool: PgPool, server_id: i32, table_name: String) -> Body {
+    spawn_export("csv", move |sender| async move {
+        write_villages_csv(&pool, server_id, &table_name, &sender).await
+    })
+}
+
+async fn write_villages_csv(
+    pool: &PgPool,
+    server_id: i32,
+    table_name: &str,
+    sender: &mpsc::Sender<Chunk>,
+) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["id", "name", "x", "y", "population", "player", "alliance", "worldid"])?;
+
+    let query = format!(
+        "SELECT id, village, x, y, population, player, alliance, worldid FROM {} WHERE server_id = $1 ORDER BY id",
+        table_name
+    );
+    let mut rows = sqlx::query(&query).bind(server_id).fetch(pool);
+    let mut buffered = 0;
+
+    while let Some(row) = rows.try_next().await? {
+        writer.write_record([
+            row.get::<i32, _>("id").to_string(),
+            row.get::<String, _>("village"),
+            row.get::<i32, _>("x").to_string(),
+            row.get::<i32, _>("y").to_string(),
+            row.get::<i32, _>("population").to_string(),
+            row.get::<Option<String>, _>("player").unwrap_or_default(),
+            row.get::<Option<String>, _>("alliance").unwrap_or_default(),
+            row.get::<Option<i32>, _>("worldid").map(|v| v.to_string()).unwrap_or_default(),
+        ])?;
+
+        buffered += 1;
+        if buffered >= ROWS_PER_CHUNK {
+            if !send_buffered(&mut writer, sender).await? {
+                return Ok(()); // Client disconnected
+            }
+            buffered = 0;
+        }
+    }
+
+    send_buffered(&mut writer, sender).await?;
+    Ok(())
+}
+
+async fn send_buffered(writer: &mut csv::Writer<Vec<u8>>, sender: &mpsc::Sender<Chunk>) -> anyhow::Result<bool> {
+    // csv::Writer has no way to drain its buffer in place, so swap in a fresh one
+    let chunk = std::mem::replace(writer, csv::Writer::from_writer(Vec::new()))
+        .into_inner()
+        .map_err(|e| anyhow::anyhow!("Failed to flush CSV buffer: {}", e))?;
+    Ok(send_chunk(sender, chunk).await)
+}
+
+pub fn villages_geojson_body(pool: PgPool, server_id: i32, table_name: String, min_population: i32) -> Body {
+    spawn_export("geojson", move |sender| async move {
+        write_villages_geojson(&pool, server_id, &table_name, min_population, &sender).await
+    })
+}
+
+async fn write_villages_geojson(
+    pool: &PgPool,
+    server_id: i32,
+    table_name: &str,
+    min_population: i32,
+    sender: &mpsc::Sender<Chunk>,
+) -> anyhow::Result<()> {
+    let query = format!(
+        "SELECT id, village, x, y, population, player, alliance, tid FROM {} WHERE server_id = $1 AND population >= $2 ORDER BY id",
+        table_name
+    );
+    let mut rows = sqlx::query(&query).bind(server_id).bind(min_population).fetch(pool);
+
+    // The FeatureCollection is written by hand around individually serialized features
+    let mut buffer = br#"{"type":"FeatureCollection","features":["#.to_vec();
+    let mut buffered = 0;
+    let mut first = true;
+
+    while let Some(row) = rows.try_next().await? {
+        if !first {
+            buffer.push(b',');
+        }
+        first = false;
+
+        let feature = serde_json::json!({
+            "type": "Feature",
+            "id": row.get::<i32, _>("id"),
+            "geometry": {
+                "type": "Point",
+                "coordinates": [row.get::<i32, _>("x"), row.get::<i32, _>("y")]
+            },
+            "properties": {
+                "name": row.get::<String, _>("village"),
+                "population": row.get::<i32, _>("population"),
+                "player": row.get::<Option<String>, _>("player"),
+                "alliance": row.get::<Option<String>, _>("alliance"),
+                "tribe": row.get::<Option<i32>, _>("tid")
+            }
+        });
+        serde_json::to_writer(&mut buffer, &feature)?;
+
+        buffered += 1;
+        if buffered >= ROWS_PER_CHUNK {
+            if !send_chunk(sender, std::mem::take(&mut buffer)).await {
+                return Ok(()); // Client disconnected
+            }
+            buffered = 0;
+        }
+    }
+
+    buffer.extend_from_slice(b"]}");
+    send_chunk(sender, buffer).await;
+    Ok(())
+}
+
+pub fn map_sql_body(pool: PgPool, server_id: i32, table_name: String) -> Body {
+    spawn_export("map.sql", move |sender| async move {
+        write_map_sql(&pool, server_id, &table_name, &sender).await
+    })
+}
+
+// Rebuilds a Travian map.sql from a stored snapshot, one INSERT per village like the
+// published dumps, so it can be re-imported here or elsewhere
+async fn write_map_sql(
+    pool: &PgPool,
+    server_id: i32,
+    table_name: &str,
+    sender: &mpsc::Sender<Chunk>,
+) -> anyhow::Result<()> {
+    let query = format!(
+        "SELECT {} FROM {} WHERE server_id = $1 ORDER BY id",
+        X_WORLD_COLUMNS.join(", "),
+        table_name
+    );
+    let mut rows = sqlx::query(&query).bind(server_id).fetch(pool);
+    let mut buffer = Vec::new();
+    let mut buffered = 0;
+
+    while let Some(row) = rows.try_next().await? {
+        let values = (0..X_WORLD_COLUMNS.len())
+            .map(|index| x_world_literal(&row, index))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        buffer.extend_from_slice(format!("INSERT INTO `x_world` VALUES ({});\n", values.join(",")).as_bytes());
+
+        buffered += 1;
+        if buffered >= ROWS_PER_CHUNK {
+            if !send_chunk(sender, std::mem::take(&mut buffer)).await {
+                return Ok(()); // Client disconnected
+            }
+            buffered = 0;
+        }
+    }
+
+    send_chunk(sender, buffer).await;
+    Ok(())
+}
+
+// Renders one column as a MySQL literal, dispatching on its Postgres type
+fn x_world_literal(row: &PgRow, index: usize) -> anyhow::Result<String> {
+    let literal = match row.columns()[index].type_info().name() {
+        "INT4" => row.try_get::<Option<i32>, _>(index)?.map(|value| value.to_string()),
+        "BOOL" => row
+            .try_get::<Option<bool>, _>(index)?
+            .map(|value| if value { "TRUE" } else { "FALSE" }.to_string()),
+        _ => row.try_get::<Option<String>, _>(index)?.map(|value| quote_sql_string(&value)),
+    };
+
+    Ok(literal.unwrap_or_else(|| "NULL".to_string()))
+}
+
+// The inverse of the unescaping in split_x_world_fields
+fn quote_sql_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for ch in value.chars() {
+        match ch {
+            '\\' => quoted.push_str("\\\\"),
+            '\'' => quoted.push_str("\\'"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+
+    async fn create_server(pool: &PgPool, name: &str) -> i32 {
+        sqlx::query_scalar("INSERT INTO servers (name, url) VALUES ($1, 'http://travian.invalid') RETURNING id")
+            .bind(name)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn snapshot_rows(pool: &PgPool, server_id: i32) -> Vec<String> {
+        let table_name = database::get_latest_table_for_server(pool, server_id).await.unwrap().unwrap();
+        let query = format!(
+            "SELECT ROW({})::TEXT FROM {} WHERE server_id = $1 ORDER BY x, y",
+            X_WORLD_COLUMNS.join(", "),
+            table_name
+        );
+        sqlx::query_scalar(&query).bind(server_id).fetch_all(pool).await.unwrap()
+    }
+
+    #[test]
+    fn quoting_escapes_what_the_parser_unescapes() {
+        assert_eq!(quote_sql_string(r"O'Brien \ Co"), r"'O\'Brien \\ Co'");
+        assert_eq!(quote_sql_string("a\tb\nc"), r"'a\tb\nc'");
+    }
+
+    #[sqlx::test]
+    async fn exported_map_sql_reimports_to_the_same_villages(pool: PgPool) {
+        database::create_tables(&pool).await.unwrap();
+        let source = create_server(&pool, "source").await;
+        let copy = create_server(&pool, "copy").await;
+
+        let dump = r#"INSERT INTO `x_world` VALUES (1,-200,200,1,10,'O\'Brien''s "Hof"',5,'Jürgen',7,'Bären, Wölfe',812,'TRUE',FALSE,NULL);
+INSERT INTO `x_world` VALUES (2,0,0,4,11,'Oasis',0,NULL,0,NULL,0,NULL,FALSE,NULL);
+INSERT INTO `x_world` VALUES (3,17,-3,2,12,'back\\slash',6,'',0,'',1045,'FALSE',TRUE,'Wonder (1)');
+"#;
+        database::execute_sql_for_server(&pool, dump, source, "test", None).await.unwrap();
+
+        let table_name = database::get_latest_table_for_server(&pool, source).await.unwrap().unwrap();
+        let exported = axum::body::to_bytes(map_sql_body(pool.clone(), source, table_name), usize::MAX).await.unwrap();
+        let stats = database::execute_sql_for_server(&pool, std::str::from_utf8(&exported).unwrap(), copy, "export", None)
+            .await
+            .unwrap();
+
+        assert_eq!(stats.layout, Some("extended"));
+        assert_eq!(stats.failed, 0);
+        let rows = snapshot_rows(&pool, source).await;
+        assert_eq!(rows.len(), 3);
+        assert_eq!(snapshot_rows(&pool, copy).await, rows);
+    }
+}
@@ -0,0 +1,127 @@
+use axum::{
+    async_trait,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+};
+use crate::error::ApiError;
+use serde::{de::DeserializeOwned, Serialize};
+
+// Drop-in replacements for axum's Json, Query and Path whose rejections (malformed
+// bodies, bad query strings, unparsable path segments) are rendered as ApiError, so
+// they get the same { "status": "error", "message": ... } body as handler errors.
+// Json is also the response type, so handlers keep writing Json(value) either way.
+pub struct Json<T>(pub T);
+
+pub struct Query<T>(pub T);
+
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = axum::Json::<T>::from_request(request, state).await?;
+        Ok(Json(value))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(value) = axum::extract::Query::<T>::from_request_parts(parts, state).await?;
+        Ok(Query(value))
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Path(value) = axum::extract::Path::<T>::from_request_parts(parts, state).await?;
+        Ok(Path(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Params {
+        limit: i64,
+    }
+
+    async fn error_body(error: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    async fn json_rejection(content_type: &str, body: &'static str) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/").header("content-type", content_type).body(Body::from(body)).unwrap();
+        match Json::<Params>::from_request(request, &()).await {
+            Ok(_) => panic!("{body} was accepted"),
+            Err(error) => error_body(error).await,
+        }
+    }
+
+    fn assert_json_error((status, json): (StatusCode, serde_json::Value), expected: StatusCode) {
+        assert_eq!(status, expected);
+        assert_eq!(json["status"], "error");
+        assert!(json["message"].as_str().is_some_and(|message| !message.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn json_rejections_are_json_errors() {
+        assert_json_error(json_rejection("application/json", "{not json").await, StatusCode::BAD_REQUEST);
+        assert_json_error(json_rejection("application/json", r#"{"limit":"x"}"#).await, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_json_error(json_rejection("text/plain", r#"{"limit":1}"#).await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let request = Request::post("/").header("content-type", "application/json").body(Body::from(r#"{"limit":1}"#)).unwrap();
+        assert!(Json::<Params>::from_request(request, &()).await.is_ok_and(|Json(params)| params.limit == 1));
+    }
+
+    #[tokio::test]
+    async fn query_rejections_are_json_errors() {
+        let (mut parts, _) = Request::get("/?limit=many").body(()).unwrap().into_parts();
+        match Query::<Params>::from_request_parts(&mut parts, &()).await {
+            Ok(_) => panic!("limit=many was accepted"),
+            Err(error) => assert_json_error(error_body(error).await, StatusCode::BAD_REQUEST),
+        }
+    }
+
+    #[tokio::test]
+    async fn internal_errors_hide_their_details() {
+        let error = ApiError::Internal(anyhow::anyhow!("relation \"villages_server_1_2024_05_01\" does not exist"));
+        let (status, json) = error_body(error).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(json["message"], "Internal server error");
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use std::time::Duration;
+
+pub const DEFAULT_FETCH_TIMEOUT_SECS: u64 = 30;
+
+// Transient failures are retried this many times in total, waiting twice as long each time
+const MAX_FETCH_ATTEMPTS: u32 = 3;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// Downloads map.sql dumps through one shared client. The timeout bounds connecting,
+// waiting for the response headers, and each wait for the next chunk of the body, so a
+// server that stops responding fails the load instead of stalling it. A slow but
+// steady download of a large dump is not cut off.
+pub struct MapFetcher {
+    client: reqwest::Client,
+    timeout: Duration,
+}
+
+impl MapFetcher {
+    pub fn new(timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .build()
+            .map_err(|e| anyhow::anyhow!("Failed to build HTTP client: {}", e))?;
+
+        Ok(MapFetcher { client, timeout })
+    }
+
+    // Connection errors and 5xx responses are retried with backoff. Any other response,
+    // including the last 5xx, is returned for the caller to inspect. Timeouts are not
+    // retried, since a hung server would otherwise hold the load for several timeouts.
+    pub async fn get(&self, url: &str) -> Result<reqwest::Response> {
+        let mut delay = INITIAL_RETRY_DELAY;
+
+        for attempt in 1..=MAX_FETCH_ATTEMPTS {
+            let last_attempt = attempt == MAX_FETCH_ATTEMPTS;
+
+            match tokio::time::timeout(self.timeout, self.client.get(url).send()).await {
+                Err(_) => return Err(self.timed_out(url)),
+                Ok(Err(e)) if e.is_timeout() => return Err(self.timed_out(url)),
+                Ok(Err(e)) if e.is_connect() && !last_attempt => {
+                    tracing::warn!(url, attempt, error = %e, "Could not connect, retrying");
+                },
+                Ok(Err(e)) => return Err(anyhow::anyhow!("Failed to fetch SQL from {}: {}", url, e)),
+                Ok(Ok(response)) if response.status().is_server_error() && !last_attempt => {
+                    tracing::warn!(url, attempt, status = %response.status(), "Server error, retrying");
+                },
+                Ok(Ok(response)) => return Ok(response),
+            }
+
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+
+        unreachable!("the last attempt always returns")
+    }
+
+    // Only looks at the status: HEAD first, then GET for servers that do not allow HEAD,
+    // dropping the body unread. A single attempt, since someone is waiting on the answer.
+    pub async fn probe(&self, url: &str) -> Result<reqwest::StatusCode> {
+        let status = self.status_of(self.client.head(url), url).await?;
+        if status == reqwest::StatusCode::METHOD_NOT_ALLOWED || status == reqwest::StatusCode::NOT_IMPLEMENTED {
+            return self.status_of(self.client.get(url), url).await;
+        }
+        Ok(status)
+    }
+
+    async fn status_of(&self, request: reqwest::RequestBuilder, url: &str) -> Result<reqwest::StatusCode> {
+        match tokio::time::timeout(self.timeout, request.send()).await {
+            Err(_) => Err(self.timed_out(url)),
+            Ok(Err(e)) if e.is_timeout() => Err(self.timed_out(url)),
+            Ok(Err(e)) => Err(anyhow::anyhow!("Could not reach {}: {}", url, e)),
+            Ok(Ok(response)) => Ok(response.status()),
+        }
+    }
+
+    pub async fn read_body(&self, mut response: reqwest::Response) -> Result<Vec<u8>> {
+        let url = response.url().to_string();
+        let mut body = Vec::new();
+
+        loop {
+            match tokio::time::timeout(self.timeout, response.chunk()).await {
+                Err(_) => return Err(self.timed_out(&url)),
+                Ok(Err(e)) if e.is_timeout() => return Err(self.timed_out(&url)),
+                Ok(Err(e)) => return Err(anyhow::anyhow!("Failed to read SQL response: {}", e)),
+                Ok(Ok(Some(chunk))) => body.extend_from_slice(&chunk),
+                Ok(Ok(None)) => return Ok(body),
+            }
+        }
+    }
+
+    fn timed_out(&self, url: &str) -> anyhow::Error {
+        anyhow::anyhow!("Timed out after {}s waiting for {}", self.timeout.as_secs(), url)
+    }
+}
@@ -0,0 +1,227 @@
+// Travian's tournament square only speeds up the part of a march beyond this many fields
+pub const TOURNAMENT_SQUARE_THRESHOLD: f64 = 20.0;
+
+// Each tournament square level adds 10% speed, up to +200% at level 20
+pub const MAX_TOURNAMENT_SQUARE_LEVEL: u32 = 20;
+
+pub fn tournament_square_bonus(level: u32) -> f64 {
+    level.min(MAX_TOURNAMENT_SQUARE_LEVEL) as f64 * 0.1
+}
+
+// Euclidean distance in fields on a map that wraps around both edges, so on a map
+// `map_size` tiles wide x=200 and x=-200 are one field apart. Matches the
+// toroidal distance the radius queries use.
+pub fn travel_distance(x1: i32, y1: i32, x2: i32, y2: i32, map_size: i32) -> f64 {
+    let dx = wrapped_delta(x1, x2, map_size);
+    let dy = wrapped_delta(y1, y2, map_size);
+    ((dx * dx + dy * dy) as f64).sqrt()
+}
+
+fn wrapped_delta(a: i32, b: i32, map_size: i32) -> i64 {
+    let size = map_size as i64;
+    let delta = (a as i64 - b as i64).abs() % size;
+    delta.min(size - delta)
+}
+
+// Seconds to cover `distance` fields at `speed` fields per hour. The first 20 fields are
+// walked at base speed; `tournament_square_bonus` (0.1 per level) speeds up the rest.
+pub fn travel_time(distance: f64, speed: f64, tournament_square_bonus: f64) -> f64 {
+    let base = distance.min(TOURNAMENT_SQUARE_THRESHOLD);
+    let boosted = (distance - TOURNAMENT_SQUARE_THRESHOLD).max(0.0);
+    let hours = base / speed + boosted / (speed * (1.0 + tournament_square_bonus));
+    hours * 3600.0
+}
+
+// Kingdoms-style region width in tiles when neither REGION_SIZE nor the request says otherwise
+pub const DEFAULT_REGION_SIZE: i32 = 50;
+
+// A square tile of the map, counted in whole regions from the south-west corner
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Region {
+    pub column: i32,
+    pub row: i32,
+}
+
+// A map `map_size` tiles wide runs from -(map_size / 2) to map_size / 2 on both axes.
+// Regions are `region_size` tiles wide starting at the lowest coordinate, so the last
+// column and row are narrower when the size does not divide the map evenly.
+pub fn regions_per_axis(map_size: i32, region_size: i32) -> i32 {
+    (map_size + region_size - 1) / region_size
+}
+
+pub fn region_of(x: i32, y: i32, map_size: i32, region_size: i32) -> Region {
+    let offset = map_size / 2;
+    // rem_euclid folds coordinates from an oversized map back onto the tiling
+    Region {
+        column: (x + offset).rem_euclid(map_size) / region_size,
+        row: (y + offset).rem_euclid(map_size) / region_size,
+    }
+}
+
+impl Region {
+    // Inclusive coordinate bounds as (min_x, max_x, min_y, max_y)
+    pub fn bounds(&self, map_size: i32, region_size: i32) -> (i32, i32, i32, i32) {
+        let offset = map_size / 2;
+        let axis = |index: i32| {
+            let min = index * region_size - offset;
+            (min, (min + region_size - 1).min(map_size - 1 - offset))
+        };
+        let (min_x, max_x) = axis(self.column);
+        let (min_y, max_y) = axis(self.row);
+        (min_x, max_x, min_y, max_y)
+    }
+}
+
+// Travian numbers tiles row by row from the north-west corner, starting at 1, so on a
+// 401-tile map (-200|200) is worldid 1 and (200|-200) is 160,801. Both conversions
+// return None for positions that are not on a map `map_size` tiles wide.
+pub fn coord_to_worldid(x: i32, y: i32, map_size: i32) -> Option<i64> {
+    let (min, max) = coordinate_range(map_size);
+    if !(min..=max).contains(&x) || !(min..=max).contains(&y) {
+        return None;
+    }
+
+    Some((max - y) as i64 * map_size as i64 + (x - min) as i64 + 1)
+}
+
+pub fn worldid_to_coord(worldid: i64, map_size: i32) -> Option<(i32, i32)> {
+    let size = map_size as i64;
+    if map_size <= 0 || worldid < 1 || worldid > size * size {
+        return None;
+    }
+
+    let (min, max) = coordinate_range(map_size);
+    let index = worldid - 1;
+    Some((min + (index % size) as i32, max - (index / size) as i32))
+}
+
+// The on-map stretches of one axis within `radius` of `center`, wrapping past the edges
+// like the distance does: on a 401-tile map, 195 with radius 10 gives 185..=200 and
+// -200..=-196. None when the window already spans the whole axis.
+pub fn wrapped_axis_ranges(center: i32, radius: i32, map_size: i32) -> Option<Vec<(i32, i32)>> {
+    if 2 * radius as i64 + 1 >= map_size as i64 {
+        return None;
+    }
+
+    let (min, max) = coordinate_range(map_size);
+    // Distances only depend on the center modulo the map size
+    let center = min + (center - min).rem_euclid(map_size);
+    let (low, high) = (center - radius, center + radius);
+
+    let mut ranges = vec![(low.max(min), high.min(max))];
+    if low < min {
+        ranges.push((low + map_size, max));
+    }
+    if high > max {
+        ranges.push((min, high - map_size));
+    }
+    Some(ranges)
+}
+
+// Inclusive bounds shared by both axes, matching the region tiling above
+pub fn coordinate_range(map_size: i32) -> (i32, i32) {
+    let min = -(map_size / 2);
+    (min, min + map_size - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_wraps_across_the_edge() {
+        assert_eq!(travel_distance(200, 0, -200, 0, 401), 1.0);
+        assert_eq!(travel_distance(0, -200, 0, 200, 401), 1.0);
+        assert_eq!(travel_distance(195, 0, -195, 0, 401), 11.0);
+        // Halfway round is the farthest two tiles can be on an axis
+        assert_eq!(travel_distance(-100, 0, 100, 0, 401), 200.0);
+        assert_eq!(travel_distance(-100, 0, 101, 0, 401), 200.0);
+    }
+
+    #[test]
+    fn axis_ranges_wrap_past_both_edges() {
+        assert_eq!(wrapped_axis_ranges(195, 10, 401), Some(vec![(185, 200), (-200, -196)]));
+        assert_eq!(wrapped_axis_ranges(-195, 10, 401), Some(vec![(-200, -185), (196, 200)]));
+        assert_eq!(wrapped_axis_ranges(0, 10, 401), Some(vec![(-10, 10)]));
+        assert_eq!(wrapped_axis_ranges(200, 1, 401), Some(vec![(199, 200), (-200, -200)]));
+    }
+
+    #[test]
+    fn axis_ranges_fold_off_map_centers_back_on() {
+        assert_eq!(wrapped_axis_ranges(201, 0, 401), Some(vec![(-200, -200)]));
+        assert_eq!(wrapped_axis_ranges(-201, 0, 401), Some(vec![(200, 200)]));
+    }
+
+    #[test]
+    fn axis_ranges_cover_the_axis_when_the_window_does() {
+        assert_eq!(wrapped_axis_ranges(0, 200, 401), None);
+        assert_eq!(wrapped_axis_ranges(0, 199, 401), Some(vec![(-199, 199)]));
+    }
+
+    #[test]
+    fn travel_time_uses_the_wrapped_distance() {
+        // Corner to opposite corner is one diagonal step
+        let distance = travel_distance(200, 200, -200, -200, 401);
+        assert!((distance - 2f64.sqrt()).abs() < 1e-9);
+        assert!((travel_time(distance, 6.0, 0.0) - distance / 6.0 * 3600.0).abs() < 1e-9);
+
+        let across = travel_distance(190, 0, -190, 0, 401);
+        assert_eq!(across, 21.0);
+        assert_eq!(travel_time(across, 6.0, 0.0), 21.0 / 6.0 * 3600.0);
+    }
+
+    #[test]
+    fn tournament_square_only_speeds_up_past_the_threshold() {
+        assert_eq!(travel_time(20.0, 10.0, tournament_square_bonus(20)), 2.0 * 3600.0);
+        // 20 fields at base speed, the other 30 at triple speed
+        assert_eq!(travel_time(50.0, 10.0, tournament_square_bonus(20)), 3.0 * 3600.0);
+        assert_eq!(tournament_square_bonus(25), tournament_square_bonus(20));
+    }
+
+    #[test]
+    fn worldid_round_trips_corners_and_center() {
+        for map_size in [200, 400, 401] {
+            let (min, max) = coordinate_range(map_size);
+            for (x, y) in [(min, min), (min, max), (max, min), (max, max), (0, 0)] {
+                let worldid = coord_to_worldid(x, y, map_size).unwrap();
+                assert_eq!(worldid_to_coord(worldid, map_size), Some((x, y)), "map_size {map_size} at ({x}|{y})");
+            }
+            assert_eq!(coord_to_worldid(min, max, map_size), Some(1));
+            assert_eq!(coord_to_worldid(max, min, map_size), Some(map_size as i64 * map_size as i64));
+        }
+    }
+
+    #[test]
+    fn worldid_rejects_positions_off_the_map() {
+        assert_eq!(coordinate_range(200), (-100, 99));
+        assert_eq!(coord_to_worldid(100, 0, 200), None);
+        assert_eq!(coord_to_worldid(0, -101, 200), None);
+        assert_eq!(worldid_to_coord(0, 400), None);
+        assert_eq!(worldid_to_coord(400 * 400 + 1, 400), None);
+    }
+
+    #[test]
+    fn regions_tile_the_map_without_gaps_or_overlaps() {
+        for (map_size, region_size) in [(401, 50), (400, 50), (401, 100), (200, 7)] {
+            let per_axis = regions_per_axis(map_size, region_size);
+            let (min, max) = coordinate_range(map_size);
+            let mut covered = vec![0u32; (map_size * map_size) as usize];
+
+            for column in 0..per_axis {
+                for row in 0..per_axis {
+                    let region = Region { column, row };
+                    let (min_x, max_x, min_y, max_y) = region.bounds(map_size, region_size);
+                    for x in min_x..=max_x {
+                        for y in min_y..=max_y {
+                            assert!((min..=max).contains(&x) && (min..=max).contains(&y));
+                            assert!(region_of(x, y, map_size, region_size) == region);
+                            covered[((x - min) * map_size + (y - min)) as usize] += 1;
+                        }
+                    }
+                }
+            }
+
+            assert!(covered.iter().all(|&count| count == 1), "map_size {map_size}, region_size {region_size}");
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{anyhow, bail, Result};
+use crate::database::ImportedVillage;
+use serde::Serialize;
+use std::collections::hash_map::{Entry, HashMap};
+
+// The columns written by the CSV export. `id` belongs to the snapshot the file came from
+// and is ignored, since villages are matched by coordinates; it and `worldid` may be
+// left out, the rest are required.
+const EXPORT_COLUMNS: [&str; 8] = ["id", "name", "x", "y", "population", "player", "alliance", "worldid"];
+
+// Same limits as the map.sql load report
+const MAX_FAILED_SAMPLES: usize = 5;
+const MAX_FAILED_SAMPLE_LENGTH: usize = 200;
+
+#[derive(Serialize, Default)]
+pub struct ImportStats {
+    pub inserted: usize,
+    pub updated: usize,
+    // Rows that matched the stored village exactly
+    pub unchanged: usize,
+    pub failed: usize,
+    // Rows dropped because a later row had the same coordinates
+    pub duplicates: usize,
+    pub failed_samples: Vec<String>,
+}
+
+impl ImportStats {
+    fn record_failure(&mut self, line: u64, reason: impl std::fmt::Display) {
+        self.failed += 1;
+        if self.failed_samples.len() < MAX_FAILED_SAMPLES {
+            self.failed_samples.push(format!("line {}: {}", line, reason).chars().take(MAX_FAILED_SAMPLE_LENGTH).collect());
+        }
+    }
+}
+
+// Column positions in the uploaded header, which may list the columns in any order
+struct Columns {
+    name: usize,
+    x: usize,
+    y: usize,
+    population: usize,
+    player: usize,
+    alliance: usize,
+    worldid: Option<usize>,
+}
+
+impl Columns {
+    fn from_header(header: &csv::StringRecord) -> Result<Self> {
+        let mut positions = HashMap::new();
+        for (index, column) in header.iter().enumerate() {
+            let column = column.trim().to_ascii_lowercase();
+            if !EXPORT_COLUMNS.contains(&column.as_str()) {
+                bail!("Unknown CSV column {:?}; expected {}", column, EXPORT_COLUMNS.join(","));
+            }
+            if positions.insert(column.clone(), index).is_some() {
+                bail!("CSV column {:?} appears more than once", column);
+            }
+        }
+
+        let required = |name: &str| {
+            positions
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow!("CSV header is missing the {:?} column", name))
+        };
+
+        Ok(Columns {
+            name: required("name")?,
+            x: required("x")?,
+            y: required("y")?,
+            population: required("population")?,
+            player: required("player")?,
+            alliance: required("alliance")?,
+            worldid: positions.get("worldid").copied(),
+        })
+    }
+
+    fn parse(&self, record: &csv::StringRecord) -> Result<ImportedVillage> {
+        let field = |index: usize| record.get(index).unwrap_or("").trim();
+        let number = |index: usize, column: &str| {
+            field(index)
+                .parse::<i32>()
+                .map_err(|_| anyhow!("{} {:?} is not a whole number", column, field(index)))
+        };
+
+        let name = field(self.name);
+        if name.is_empty() {
+            bail!("name is empty");
+        }
+        let population = number(self.population, "population")?;
+        if population < 0 {
+            bail!("population {} is negative", population);
+        }
+        let worldid = match self.worldid {
+            Some(index) if !field(index).is_empty() => Some(number(index, "worldid")?),
+            _ => None,
+        };
+
+        Ok(ImportedVillage {
+            name: name.to_string(),
+            x: number(self.x, "x")?,
+            y: number(self.y, "y")?,
+            population,
+            player: field(self.player).to_string(),
+            alliance: field(self.alliance).to_string(),
+            worldid,
+        })
+    }
+}
+
+// Reads an uploaded villages CSV. A bad header rejects the whole file; a bad row is
+// counted as failed and skipped. As with map.sql loads, the last row for a coordinate
+// wins, keeping the position of the first.
+pub fn parse_villages_csv(data: &[u8]) -> Result<(Vec<ImportedVillage>, ImportStats)> {
+    let mut reader = csv::ReaderBuilder::new().from_reader(data);
+    let columns = Columns::from_header(reader.headers().map_err(|e| anyhow!("Invalid CSV header: {}", e))?)?;
+
+    let mut stats = ImportStats::default();
+    let mut villages: Vec<ImportedVillage> = Vec::new();
+    let mut positions: HashMap<(i32, i32), usize> = HashMap::new();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map(|position| position.line()).unwrap_or_default();
+                stats.record_failure(line, e);
+                continue;
+            },
+        };
+        let line = record.position().map(|position| position.line()).unwrap_or_default();
+
+        match columns.parse(&record) {
+            Ok(village) => match positions.entry((village.x, village.y)) {
+                Entry::Occupied(entry) => {
+                    villages[*entry.get()] = village;
+                    stats.duplicates += 1;
+                },
+                Entry::Vacant(entry) => {
+                    entry.insert(villages.len());
+                    villages.push(village);
+                },
+            },
+            Err(e) => stats.record_failure(line, e),
+        }
+    }
+
+    Ok((villages, stats))
+}
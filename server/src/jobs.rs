@@ -0,0 +1,216 @@
+use crate::cache::WorldInfoCache;
+use crate::database::{self, LoadProgress, LoadSettings, LoadStats, Server};
+use crate::fetch::MapFetcher;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{watch, OwnedMutexGuard};
+
+// Finished jobs beyond this many are forgotten, oldest first
+const MAX_RETAINED_JOBS: usize = 100;
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LoadJob {
+    pub id: u64,
+    pub server_id: i32,
+    pub state: JobState,
+    pub inserted: Option<usize>,
+    pub message: Option<String>,
+    // Parse statistics, when a dump was actually loaded
+    pub load_stats: Option<LoadStats>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl LoadJob {
+    fn is_finished(&self) -> bool {
+        matches!(self.state, JobState::Done | JobState::Failed)
+    }
+}
+
+// In-memory record of background data loads, so a slow map.sql fetch runs outside the
+// request that started it and clients poll for the result. Lost on restart.
+pub struct LoadJobs {
+    registry: Mutex<JobRegistry>,
+    // Shared with the scheduler, so job loads are announced and cleaned up like its own
+    settings: LoadSettings,
+}
+
+#[derive(Default)]
+struct JobRegistry {
+    next_id: u64,
+    jobs: BTreeMap<u64, JobEntry>,
+}
+
+struct JobEntry {
+    job: LoadJob,
+    // Closes once the job's final state is recorded
+    progress: watch::Receiver<LoadProgress>,
+}
+
+impl LoadJobs {
+    pub fn new(settings: LoadSettings) -> Self {
+        LoadJobs { registry: Mutex::default(), settings }
+    }
+
+    pub fn get(&self, id: u64) -> Option<LoadJob> {
+        self.registry.lock().unwrap().jobs.get(&id).map(|entry| entry.job.clone())
+    }
+
+    // Follows the job's progress. The receiver is closed once the job has finished, so
+    // subscribing to a finished job only yields its last progress.
+    pub fn subscribe(&self, id: u64) -> Option<watch::Receiver<LoadProgress>> {
+        self.registry.lock().unwrap().jobs.get(&id).map(|entry| entry.progress.clone())
+    }
+
+    fn create(&self, server_id: i32, progress: watch::Receiver<LoadProgress>) -> u64 {
+        let mut registry = self.registry.lock().unwrap();
+
+        // Ids only grow, so the first finished job found is the oldest
+        while registry.jobs.len() >= MAX_RETAINED_JOBS {
+            let Some(oldest) = registry.jobs.values().find(|entry| entry.job.is_finished()).map(|entry| entry.job.id) else {
+                break;
+            };
+            registry.jobs.remove(&oldest);
+        }
+
+        registry.next_id += 1;
+        let id = registry.next_id;
+        let job = LoadJob {
+            id,
+            server_id,
+            state: JobState::Pending,
+            inserted: None,
+            message: None,
+            load_stats: None,
+            created_at: chrono::Utc::now(),
+            finished_at: None,
+        };
+        registry.jobs.insert(id, JobEntry { job, progress });
+        id
+    }
+
+    fn update(&self, id: u64, apply: impl FnOnce(&mut LoadJob)) {
+        if let Some(entry) = self.registry.lock().unwrap().jobs.get_mut(&id) {
+            apply(&mut entry.job);
+        }
+    }
+}
+
+// Runs the load on its own task and returns the job id right away. The caller's load
+// lock moves into the task, so the server stays locked until the load finishes.
+pub fn spawn_load(
+    jobs: Arc<LoadJobs>,
+    pool: PgPool,
+    fetcher: Arc<MapFetcher>,
+    cache: Arc<WorldInfoCache>,
+    server: Server,
+    guard: OwnedMutexGuard<()>,
+) -> u64 {
+    spawn(jobs, pool, fetcher, cache, server, guard, false)
+}
+
+// Like spawn_load, but reloads even when today's snapshot exists
+pub fn spawn_reload(
+    jobs: Arc<LoadJobs>,
+    pool: PgPool,
+    fetcher: Arc<MapFetcher>,
+    cache: Arc<WorldInfoCache>,
+    server: Server,
+    guard: OwnedMutexGuard<()>,
+) -> u64 {
+    spawn(jobs, pool, fetcher, cache, server, guard, true)
+}
+
+fn spawn(
+    jobs: Arc<LoadJobs>,
+    pool: PgPool,
+    fetcher: Arc<MapFetcher>,
+    cache: Arc<WorldInfoCache>,
+    server: Server,
+    guard: OwnedMutexGuard<()>,
+    reload: bool,
+) -> u64 {
+    let (progress, receiver) = watch::channel(LoadProgress::default());
+    let id = jobs.create(server.id, receiver);
+
+    tokio::spawn(async move {
+        let _guard = guard;
+        jobs.update(id, |job| job.state = JobState::Running);
+
+        let result = if reload {
+            database::reload_data_for_server(&pool, &fetcher, &jobs.settings, &server, Some(&progress)).await
+        } else {
+            database::load_data_for_server(&pool, &fetcher, &jobs.settings, &server, Some(&progress)).await
+        };
+        let (state, stats, message) = match result {
+            Ok(outcome) => {
+                tracing::info!(job_id = id, server_id = server.id, "Load job finished: {}", outcome.message);
+                (JobState::Done, outcome.stats, outcome.message)
+            },
+            Err(e) => {
+                tracing::error!(job_id = id, server_id = server.id, error = %e, "Load job failed");
+                (JobState::Failed, None, e.to_string())
+            }
+        };
+
+        // A same-day reload keeps the snapshot date, so drop the cached aggregates explicitly
+        cache.invalidate(server.id);
+        jobs.update(id, |job| {
+            job.state = state;
+            job.inserted = stats.as_ref().map(|stats| stats.inserted);
+            job.load_stats = stats;
+            job.message = Some(message);
+            job.finished_at = Some(chrono::Utc::now());
+        });
+        // Subscribers that see the channel close can now read the finished job
+        drop(progress);
+    });
+
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_lock::ServerLoadLocks;
+    use std::time::Duration;
+
+    #[sqlx::test]
+    async fn subscribers_see_the_job_finish(pool: PgPool) {
+        database::create_tables(&pool).await.unwrap();
+        // Nothing listens on port 1, so the fetch fails straight away
+        let server = database::add_server(&pool, "unreachable", "http://127.0.0.1:1").await.unwrap();
+        let jobs = Arc::new(LoadJobs::new(LoadSettings { webhook: None, table_retention_days: database::DEFAULT_TABLE_RETENTION_DAYS }));
+        let locks = ServerLoadLocks::new();
+        let fetcher = Arc::new(MapFetcher::new(Duration::from_secs(1)).unwrap());
+        let cache = Arc::new(WorldInfoCache::new(1));
+
+        let guard = locks.try_lock(server.id).unwrap();
+        let id = spawn_reload(jobs.clone(), pool, fetcher, cache, server.clone(), guard);
+        let mut progress = jobs.subscribe(id).unwrap();
+        while progress.changed().await.is_ok() {}
+
+        let job = jobs.get(id).unwrap();
+        assert!(matches!(job.state, JobState::Failed));
+        assert!(job.finished_at.is_some());
+        assert!(locks.try_lock(server.id).is_ok());
+
+        // A late subscriber still gets the last progress before the channel closes
+        let mut progress = jobs.subscribe(id).unwrap();
+        progress.mark_changed();
+        assert!(progress.changed().await.is_ok());
+        assert!(progress.changed().await.is_err());
+        assert!(jobs.subscribe(id + 1).is_none());
+    }
+}
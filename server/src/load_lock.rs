@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+// One async lock per server, held for the whole fetch-and-replace of today's
+// snapshot so activation, the scheduler, and manual reloads never interleave
+// deletes and inserts on the same table.
+#[derive(Default)]
+pub struct ServerLoadLocks {
+    locks: Mutex<HashMap<i32, Arc<AsyncMutex<()>>>>,
+}
+
+impl ServerLoadLocks {
+    pub fn new() -> Self {
+        ServerLoadLocks::default()
+    }
+
+    fn lock_for(&self, server_id: i32) -> Arc<AsyncMutex<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        locks.entry(server_id).or_default().clone()
+    }
+
+    // Fails fast with LoadInProgress instead of queueing behind a running load
+    pub fn try_lock(&self, server_id: i32) -> Result<OwnedMutexGuard<()>, LoadInProgress> {
+        self.lock_for(server_id)
+            .try_lock_owned()
+            .map_err(|_| LoadInProgress { server_id })
+    }
+
+    pub fn remove(&self, server_id: i32) {
+        self.locks.lock().unwrap().remove(&server_id);
+    }
+
+    // Resolves once every load that was running when called has released its lock
+    pub async fn wait_idle(&self) {
+        let locks: Vec<_> = self.locks.lock().unwrap().values().cloned().collect();
+        for lock in locks {
+            drop(lock.lock().await);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LoadInProgress {
+    pub server_id: i32,
+}
+
+impl std::fmt::Display for LoadInProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "A data load for server {} is already in progress", self.server_id)
+    }
+}
+
+impl std::error::Error for LoadInProgress {}
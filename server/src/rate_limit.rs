@@ -0,0 +1,182 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use crate::error::ApiError;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 300;
+
+// Health checks from monitors and load balancers get this many times the normal allowance
+const HEALTH_RATE_LIMIT_MULTIPLIER: u32 = 10;
+
+// Hard cap on tracked clients; past it the least recently seen tenth is dropped at once
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// How often idle clients are swept out of the map
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct RateLimits {
+    default: TokenBuckets,
+    health: TokenBuckets,
+}
+
+impl RateLimits {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimits {
+            default: TokenBuckets::new(per_minute),
+            health: TokenBuckets::new(per_minute.saturating_mul(HEALTH_RATE_LIMIT_MULTIPLIER)),
+        }
+    }
+}
+
+// Limits are per peer address; behind a reverse proxy every client shares the proxy's
+pub async fn limit_requests(
+    State(limits): State<Arc<RateLimits>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let buckets = if request.uri().path() == "/health" {
+        &limits.health
+    } else {
+        &limits.default
+    };
+
+    buckets.take(peer.ip(), Instant::now())?;
+    Ok(next.run(request).await)
+}
+
+// Each client starts with a full minute's allowance, which refills continuously. A
+// client unseen for as long as a refill takes would be back to a full bucket anyway, so
+// the periodic sweep forgets it; the cap bounds the map between sweeps.
+struct TokenBuckets {
+    capacity: f64,
+    refill_per_sec: f64,
+    clients: Mutex<Clients>,
+}
+
+struct Clients {
+    buckets: HashMap<IpAddr, Bucket>,
+    swept_at: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    // Doubles as the last time the client was seen
+    refilled_at: Instant,
+}
+
+impl TokenBuckets {
+    fn new(per_minute: u32) -> Self {
+        TokenBuckets {
+            capacity: per_minute as f64,
+            refill_per_sec: per_minute as f64 / 60.0,
+            clients: Mutex::new(Clients {
+                buckets: HashMap::new(),
+                swept_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn take(&self, client: IpAddr, now: Instant) -> Result<(), ApiError> {
+        let mut clients = self.clients.lock().unwrap();
+
+        if now.duration_since(clients.swept_at) >= SWEEP_INTERVAL {
+            let full_after = Duration::from_secs_f64(self.capacity / self.refill_per_sec);
+            clients.buckets.retain(|_, bucket| now.duration_since(bucket.refilled_at) < full_after);
+            clients.swept_at = now;
+        }
+
+        if clients.buckets.len() >= MAX_TRACKED_CLIENTS && !clients.buckets.contains_key(&client) {
+            evict_least_recently_seen(&mut clients.buckets, MAX_TRACKED_CLIENTS / 10);
+        }
+
+        let bucket = clients.buckets.entry(client).or_insert(Bucket {
+            tokens: self.capacity,
+            refilled_at: now,
+        });
+
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.refill_per_sec;
+            Err(ApiError::TooManyRequests {
+                retry_after: Duration::from_secs_f64(wait),
+            })
+        }
+    }
+}
+
+fn evict_least_recently_seen(buckets: &mut HashMap<IpAddr, Bucket>, count: usize) {
+    let mut seen: Vec<(Instant, IpAddr)> = buckets.iter().map(|(client, bucket)| (bucket.refilled_at, *client)).collect();
+    let count = count.clamp(1, seen.len());
+    seen.select_nth_unstable(count - 1);
+    for (_, client) in &seen[..count] {
+        buckets.remove(client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn client(index: u32) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::from(index))
+    }
+
+    fn tracked(buckets: &TokenBuckets) -> usize {
+        buckets.clients.lock().unwrap().buckets.len()
+    }
+
+    #[test]
+    fn allowance_runs_out_and_refills() {
+        let buckets = TokenBuckets::new(60);
+        let start = Instant::now();
+        for _ in 0..60 {
+            assert!(buckets.take(client(1), start).is_ok());
+        }
+        assert!(matches!(buckets.take(client(1), start), Err(ApiError::TooManyRequests { .. })));
+        assert!(buckets.take(client(2), start).is_ok());
+        assert!(buckets.take(client(1), start + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn sweep_forgets_idle_clients() {
+        let buckets = TokenBuckets::new(60);
+        let start = Instant::now();
+        for index in 0..100 {
+            buckets.take(client(index), start).unwrap();
+        }
+        buckets.take(client(1000), start + Duration::from_secs(30)).unwrap();
+
+        // Refilling takes 60 s; only the client seen 30 s before the sweep is kept
+        buckets.take(client(1001), start + SWEEP_INTERVAL + Duration::from_secs(1)).unwrap();
+        assert_eq!(tracked(&buckets), 2);
+    }
+
+    #[test]
+    fn busy_clients_never_exceed_the_cap() {
+        let buckets = TokenBuckets::new(60);
+        let start = Instant::now();
+        for index in 0..(MAX_TRACKED_CLIENTS as u32 * 2) {
+            buckets.take(client(index), start + Duration::from_millis(index as u64)).unwrap();
+            assert!(tracked(&buckets) <= MAX_TRACKED_CLIENTS);
+        }
+
+        // The most recent clients are the ones kept
+        let clients = buckets.clients.lock().unwrap();
+        assert!(clients.buckets.contains_key(&client(MAX_TRACKED_CLIENTS as u32 * 2 - 1)));
+        assert!(!clients.buckets.contains_key(&client(0)));
+    }
+}
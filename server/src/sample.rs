@@ -0,0 +1,251 @@
+use crate::database::NATARS_TRIBE_ID;
+use crate::geo;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+
+// Roughly how a real world looks: accounts average a handful of villages, alliances a
+// dozen accounts, and a fifth of accounts have stopped playing
+const VILLAGES_PER_PLAYER: usize = 6;
+const PLAYERS_PER_ALLIANCE: usize = 12;
+const UNALIGNED_PLAYER_PERCENT: u32 = 20;
+const INACTIVE_PLAYER_PERCENT: u32 = 20;
+const NATARS_VILLAGE_PERCENT: u32 = 2;
+const WORLD_WONDERS_PER_VILLAGES: usize = 1000;
+
+// How far, in fields, members settle from their alliance's home and villages from
+// their owner's first village
+const ALLIANCE_SPREAD: i32 = 25;
+const PLAYER_SPREAD: i32 = 7;
+const PLACEMENT_ATTEMPTS: usize = 20;
+
+// Weights for Romans, Teutons, Gauls, Egyptians and Huns
+const TRIBES: [(i32, u32); 5] = [(1, 30), (2, 30), (3, 30), (6, 5), (7, 5)];
+const NATARS_UID: i32 = 1;
+
+const NAME_SYLLABLES: [&str; 20] = [
+    "ka", "ro", "mi", "dra", "ven", "tor", "li", "sha", "gor", "el",
+    "bri", "nax", "ul", "ze", "fa", "quin", "mor", "tha", "is", "vek",
+];
+
+// A village the seeder made up, as it stands today. Earlier snapshots are derived from it:
+// the village only exists for the last `age_days` days and had `growth_per_day` less
+// population for every day back.
+pub struct SampleVillage {
+    pub x: i32,
+    pub y: i32,
+    pub worldid: Option<i32>,
+    pub tid: i32,
+    pub vid: i32,
+    pub name: String,
+    pub uid: i32,
+    pub player: String,
+    pub aid: Option<i32>,
+    pub alliance: Option<String>,
+    pub population: i32,
+    pub capital: bool,
+    pub ww_name: Option<String>,
+    pub age_days: usize,
+    pub growth_per_day: i32,
+}
+
+impl SampleVillage {
+    pub fn population_days_ago(&self, days_ago: usize) -> Option<i32> {
+        (days_ago < self.age_days).then(|| (self.population - self.growth_per_day * days_ago as i32).max(2))
+    }
+}
+
+// Ids already taken in the tables being seeded; generated ids start above them
+#[derive(Default)]
+pub struct IdOffsets {
+    pub uid: i32,
+    pub aid: i32,
+    pub vid: i32,
+}
+
+struct SamplePlayer {
+    uid: i32,
+    name: String,
+    tid: i32,
+    alliance: Option<usize>,
+    home: Option<(i32, i32)>,
+    villages: usize,
+    growth: bool,
+}
+
+// Up to `count` villages on free tiles of a `map_size` map. Fewer come back when the
+// map is too crowded to place them. The same seed and inputs give the same villages.
+pub fn generate_villages(
+    count: usize,
+    days: usize,
+    seed: Option<u64>,
+    map_size: i32,
+    occupied: &mut HashSet<(i32, i32)>,
+    offsets: &IdOffsets,
+) -> Vec<SampleVillage> {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let (min, max) = geo::coordinate_range(map_size);
+    let mut names = HashSet::new();
+
+    let alliance_count = (count / (VILLAGES_PER_PLAYER * PLAYERS_PER_ALLIANCE)).max(1);
+    let alliances: Vec<(String, (i32, i32))> = (0..alliance_count)
+        .map(|_| (unique_name(&mut rng, &mut names, true), central_point(&mut rng, min, max)))
+        .collect();
+
+    let player_count = (count / VILLAGES_PER_PLAYER).max(1);
+    let mut players: Vec<SamplePlayer> = (0..player_count)
+        .map(|index| SamplePlayer {
+            uid: offsets.uid.max(NATARS_UID) + 1 + index as i32,
+            name: unique_name(&mut rng, &mut names, false),
+            tid: weighted_tribe(&mut rng),
+            alliance: (rng.gen_range(0..100) >= UNALIGNED_PLAYER_PERCENT).then(|| rng.gen_range(0..alliance_count)),
+            home: None,
+            villages: 0,
+            growth: rng.gen_range(0..100) >= INACTIVE_PLAYER_PERCENT,
+        })
+        .collect();
+
+    let mut villages = Vec::with_capacity(count);
+    let world_wonders = count / WORLD_WONDERS_PER_VILLAGES;
+
+    for index in 0..count {
+        let vid = offsets.vid + 1 + index as i32;
+
+        // The Natars hold a few scattered villages, including the world wonders
+        if index < world_wonders || rng.gen_range(0..100) < NATARS_VILLAGE_PERCENT {
+            let Some((x, y)) = place(&mut rng, occupied, min, max, None) else {
+                continue;
+            };
+            let is_ww = index < world_wonders;
+            villages.push(SampleVillage {
+                x,
+                y,
+                worldid: worldid(x, y, map_size),
+                tid: NATARS_TRIBE_ID,
+                vid,
+                name: if is_ww { format!("Natars {}|{}", x, y) } else { "Natars village".to_string() },
+                uid: NATARS_UID,
+                player: "Natars".to_string(),
+                aid: None,
+                alliance: None,
+                population: rng.gen_range(200..=900),
+                capital: false,
+                ww_name: is_ww.then(|| format!("World Wonder {}", index + 1)),
+                age_days: days,
+                growth_per_day: 0,
+            });
+            continue;
+        }
+
+        // Squaring skews villages towards the first accounts, giving a few large ones
+        let owner = ((rng.gen::<f64>().powi(2) * player_count as f64) as usize).min(player_count - 1);
+        let player = &mut players[owner];
+        let around = match player.home {
+            Some(home) => Some((home, PLAYER_SPREAD)),
+            None => player.alliance.map(|alliance| (alliances[alliance].1, ALLIANCE_SPREAD)),
+        };
+        let Some((x, y)) = place(&mut rng, occupied, min, max, around) else {
+            continue;
+        };
+
+        let capital = player.home.is_none();
+        player.home.get_or_insert((x, y));
+        player.villages += 1;
+
+        villages.push(SampleVillage {
+            x,
+            y,
+            worldid: worldid(x, y, map_size),
+            tid: player.tid,
+            vid,
+            name: if capital { format!("{}'s village", player.name) } else { format!("New village {}", player.villages) },
+            uid: player.uid,
+            player: player.name.clone(),
+            aid: player.alliance.map(|alliance| offsets.aid + 1 + alliance as i32),
+            alliance: player.alliance.map(|alliance| alliances[alliance].0.clone()),
+            population: if capital { rng.gen_range(300..=1200) } else { rng.gen_range(20..=900) },
+            capital,
+            ww_name: None,
+            // Capitals predate the seeded history; later villages may have been founded during it
+            age_days: if capital { days } else { rng.gen_range(1..=days * 2).min(days) },
+            growth_per_day: if player.growth { rng.gen_range(1..=15) } else { 0 },
+        });
+    }
+
+    villages
+}
+
+fn worldid(x: i32, y: i32, map_size: i32) -> Option<i32> {
+    geo::coord_to_worldid(x, y, map_size).and_then(|worldid| i32::try_from(worldid).ok())
+}
+
+// Picks a free tile within `spread` fields of a point, or anywhere towards the middle of
+// the map when there is no point yet. Claims the tile in `occupied`.
+fn place(
+    rng: &mut StdRng,
+    occupied: &mut HashSet<(i32, i32)>,
+    min: i32,
+    max: i32,
+    around: Option<((i32, i32), i32)>,
+) -> Option<(i32, i32)> {
+    for _ in 0..PLACEMENT_ATTEMPTS {
+        let (x, y) = match around {
+            Some(((cx, cy), spread)) => (
+                (cx + rng.gen_range(-spread..=spread)).clamp(min, max),
+                (cy + rng.gen_range(-spread..=spread)).clamp(min, max),
+            ),
+            None => central_point(rng, min, max),
+        };
+        if occupied.insert((x, y)) {
+            return Some((x, y));
+        }
+    }
+    None
+}
+
+// Worlds fill up from the middle outwards, so points get sparser away from (0|0)
+fn central_point(rng: &mut StdRng, min: i32, max: i32) -> (i32, i32) {
+    let radius = rng.gen::<f64>() * max as f64;
+    let angle = rng.gen::<f64>() * std::f64::consts::TAU;
+    (
+        ((radius * angle.cos()).round() as i32).clamp(min, max),
+        ((radius * angle.sin()).round() as i32).clamp(min, max),
+    )
+}
+
+fn weighted_tribe(rng: &mut StdRng) -> i32 {
+    let total: u32 = TRIBES.iter().map(|(_, weight)| weight).sum();
+    let mut roll = rng.gen_range(0..total);
+    for (tid, weight) in TRIBES {
+        if roll < weight {
+            return tid;
+        }
+        roll -= weight;
+    }
+    TRIBES[0].0
+}
+
+// Player names are a few syllables ("Kadraven"); alliance names are short upper-case tags
+fn unique_name(rng: &mut StdRng, taken: &mut HashSet<String>, alliance: bool) -> String {
+    loop {
+        let syllables = rng.gen_range(2..=3);
+        let name: String = (0..syllables)
+            .map(|_| NAME_SYLLABLES[rng.gen_range(0..NAME_SYLLABLES.len())])
+            .collect();
+        let name = if alliance {
+            name.to_uppercase().chars().take(4).collect()
+        } else {
+            let mut chars = name.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+        };
+
+        // Numbered once the short names run out
+        let name = if taken.contains(&name) { format!("{}{}", name, taken.len()) } else { name };
+        if taken.insert(name.clone()) {
+            return name;
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::cache::WorldInfoCache;
+use crate::database::{self, LoadSettings};
+use crate::fetch::MapFetcher;
+use crate::load_lock::{LoadInProgress, ServerLoadLocks};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+// Travian publishes a fresh map.sql once a day
+pub const DEFAULT_AUTO_LOAD_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+pub fn spawn_auto_load_task(
+    pool: PgPool,
+    cache: Arc<WorldInfoCache>,
+    load_locks: Arc<ServerLoadLocks>,
+    fetcher: Arc<MapFetcher>,
+    settings: LoadSettings,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            // The first tick completes immediately, so stale servers refresh on startup
+            tokio::select! {
+                _ = ticker.tick() => {},
+                _ = shutdown.changed() => break,
+            }
+            run_scheduled_auto_load(&pool, &cache, &load_locks, &fetcher, &settings, &shutdown).await;
+        }
+    })
+}
+
+// Stops between servers once shutdown starts; a load already underway is finished
+async fn run_scheduled_auto_load(
+    pool: &PgPool,
+    cache: &WorldInfoCache,
+    load_locks: &ServerLoadLocks,
+    fetcher: &MapFetcher,
+    settings: &LoadSettings,
+    shutdown: &watch::Receiver<bool>,
+) {
+    let servers = match database::get_all_servers(pool).await {
+        Ok(servers) => servers,
+        Err(e) => {
+            tracing::error!(error = %e, "Scheduled auto-load could not list servers");
+            return;
+        }
+    };
+
+    for server in servers {
+        if *shutdown.borrow() {
+            return;
+        }
+
+        match database::is_new_data_needed_for_server(pool, server.id).await {
+            Ok(true) => {},
+            Ok(false) => continue,
+            Err(e) => {
+                tracing::error!(server_id = server.id, server = %server.name, error = %e, "Scheduled auto-load could not check data freshness");
+                continue;
+            }
+        }
+
+        match database::auto_load_data_for_server(pool, load_locks, fetcher, settings, &server).await {
+            Ok(outcome) => {
+                cache.invalidate(server.id);
+                tracing::info!(server_id = server.id, server = %server.name, "Scheduled auto-load: {}", outcome.message);
+            },
+            Err(e) if e.is::<LoadInProgress>() => {
+                tracing::info!(server_id = server.id, server = %server.name, "Skipping scheduled auto-load, a load is already running");
+            },
+            Err(e) => {
+                tracing::error!(server_id = server.id, server = %server.name, error = %e, "Scheduled auto-load failed");
+            }
+        }
+    }
+}
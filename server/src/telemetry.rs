@@ -0,0 +1,65 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::time::Instant;
+
+// Metrics are off by default, since /metrics is served without the API key. Unless
+// METRICS_ENABLED=true the recorder is never installed, which turns every metrics
+// macro into a no-op and leaves /metrics unrouted.
+pub fn install_prometheus_recorder() -> anyhow::Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new().install_recorder()?;
+    Ok(handle)
+}
+
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    // Label by route template rather than the raw URI to keep label cardinality bounded
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("path", path),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels).record(started.elapsed().as_secs_f64());
+
+    response
+}
+
+// Records db_query_duration_seconds{query} when dropped, so early returns are timed too
+pub struct QueryTimer {
+    query: &'static str,
+    started: Instant,
+}
+
+impl QueryTimer {
+    pub fn start(query: &'static str) -> Self {
+        QueryTimer { query, started: Instant::now() }
+    }
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        metrics::histogram!("db_query_duration_seconds", "query" => self.query).record(self.started.elapsed().as_secs_f64());
+    }
+}
+
+pub fn record_villages_loaded(server_id: i32, village_count: usize) {
+    metrics::gauge!("villages_loaded", "server_id" => server_id.to_string()).set(village_count as f64);
+}
+
+pub fn record_auto_load_success(server_id: i32) {
+    metrics::gauge!("last_auto_load_timestamp_seconds", "server_id" => server_id.to_string())
+        .set(chrono::Utc::now().timestamp() as f64);
+}
@@ -0,0 +1,218 @@
+use anyhow::Result;
+use crate::database::{self, LoadStats, Server, WatchChangeKind, WatchlistChange};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Failed deliveries are retried this many times in total, waiting twice as long each time
+const MAX_WEBHOOK_ATTEMPTS: u32 = 3;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Watchlist changes listed in one notification; the rest are only counted
+const MAX_NOTIFIED_CHANGES: i64 = 20;
+
+// Discord rejects messages longer than this
+const DISCORD_MAX_CONTENT_LENGTH: usize = 2000;
+
+#[derive(Clone, Copy, Debug)]
+pub enum WebhookFormat {
+    // The LoadSummary itself
+    Json,
+    // { "content": ... } with a readable message, as Discord webhooks expect
+    Discord,
+}
+
+impl FromStr for WebhookFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "json" => Ok(WebhookFormat::Json),
+            "discord" => Ok(WebhookFormat::Discord),
+            _ => Err("expected json or discord".to_string()),
+        }
+    }
+}
+
+// What a notification reports about one finished load
+#[derive(Serialize)]
+pub struct LoadSummary {
+    pub event: &'static str,
+    pub server_id: i32,
+    pub server: String,
+    pub date: Option<chrono::NaiveDate>,
+    pub village_count: usize,
+    pub failed: usize,
+    // Changes this load recorded at watched coordinates, ownership changes, new and
+    // abandoned villages before population changes, at most MAX_NOTIFIED_CHANGES
+    pub watchlist_changes: Vec<WatchlistChange>,
+    pub watchlist_change_count: usize,
+}
+
+impl LoadSummary {
+    // The snapshot the load wrote is the server's latest, and the watchlist changes the
+    // load recorded are the ones ending on it
+    pub async fn collect(pool: &PgPool, server: &Server, stats: &LoadStats) -> Result<Self> {
+        let date = database::get_latest_data_date_for_server(pool, server.id).await?;
+        let mut changes = match date {
+            Some(date) => {
+                let filter = database::WatchlistChangeFilter { since: Some(date), kind: None, limit: i64::MAX };
+                database::get_watchlist_changes(pool, server.id, &filter).await?
+            },
+            None => Vec::new(),
+        };
+        let watchlist_change_count = changes.len();
+        changes.sort_by_key(|change| matches!(change.kind, WatchChangeKind::Population));
+        changes.truncate(MAX_NOTIFIED_CHANGES as usize);
+
+        Ok(LoadSummary {
+            event: "load",
+            server_id: server.id,
+            server: server.name.clone(),
+            date,
+            village_count: stats.inserted,
+            failed: stats.failed,
+            watchlist_changes: changes,
+            watchlist_change_count,
+        })
+    }
+}
+
+// Posts load summaries to WEBHOOK_URL. Delivery runs on its own task, so a slow or
+// failing endpoint never holds up loading; failures are only logged.
+pub struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    format: WebhookFormat,
+}
+
+impl Webhook {
+    pub fn new(url: String, format: WebhookFormat) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .map_err(|e| anyhow::anyhow!("Failed to build HTTP client: {}", e))?;
+
+        Ok(Webhook { client, url, format })
+    }
+
+    pub fn notify(self: &Arc<Self>, summary: LoadSummary) {
+        let body = match self.format {
+            WebhookFormat::Json => serde_json::to_value(&summary).unwrap_or_default(),
+            WebhookFormat::Discord => serde_json::json!({ "content": discord_message(&summary) }),
+        };
+        let webhook = self.clone();
+        let server_id = summary.server_id;
+
+        tokio::spawn(async move {
+            match webhook.deliver(&body).await {
+                Ok(()) => tracing::info!(server_id, "Webhook notified of load"),
+                Err(e) => tracing::warn!(server_id, error = %e, "Webhook notification failed"),
+            }
+        });
+    }
+
+    // Connection errors, timeouts, 429 and 5xx responses are retried with backoff; any
+    // other unsuccessful status means the endpoint will not take the notification
+    async fn deliver(&self, body: &serde_json::Value) -> Result<()> {
+        let mut delay = INITIAL_RETRY_DELAY;
+
+        for attempt in 1..=MAX_WEBHOOK_ATTEMPTS {
+            let last_attempt = attempt == MAX_WEBHOOK_ATTEMPTS;
+
+            match self.client.post(&self.url).json(body).send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    let retryable = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                    if !retryable || last_attempt {
+                        return Err(anyhow::anyhow!("Webhook responded with {}", status));
+                    }
+                    tracing::warn!(attempt, status = %status, "Webhook delivery failed, retrying");
+                },
+                Err(e) if last_attempt => return Err(anyhow::anyhow!("Could not reach webhook: {}", e)),
+                Err(e) => tracing::warn!(attempt, error = %e, "Could not reach webhook, retrying"),
+            }
+
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+
+        unreachable!("the last attempt always returns")
+    }
+}
+
+// One line for the load, then one per listed watchlist change
+fn discord_message(summary: &LoadSummary) -> String {
+    let date = summary.date.map(|date| format!(" for {}", date)).unwrap_or_default();
+    let mut lines = vec![format!("**{}**: loaded {} villages{}", summary.server, summary.village_count, date)];
+    if summary.failed > 0 {
+        lines[0].push_str(&format!(" ({} rows failed)", summary.failed));
+    }
+
+    for change in &summary.watchlist_changes {
+        lines.push(format!("• {}", describe_change(change)));
+    }
+    if summary.watchlist_change_count > summary.watchlist_changes.len() {
+        lines.push(format!(
+            "…and {} more watchlist changes",
+            summary.watchlist_change_count - summary.watchlist_changes.len()
+        ));
+    }
+
+    let mut content = String::new();
+    for line in lines {
+        // Whole lines only, so a cut never lands inside a name
+        if content.chars().count() + line.chars().count() + 1 > DISCORD_MAX_CONTENT_LENGTH {
+            break;
+        }
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content.push_str(&line);
+    }
+    content
+}
+
+fn describe_change(change: &WatchlistChange) -> String {
+    let place = match &change.label {
+        Some(label) => format!("{} ({}|{})", label, change.x, change.y),
+        None => format!("({}|{})", change.x, change.y),
+    };
+    let owner = |player: &Option<String>| player.clone().filter(|player| !player.is_empty()).unwrap_or_else(|| "nobody".to_string());
+    let population = |population: Option<i32>| population.unwrap_or_default();
+
+    match change.kind {
+        WatchChangeKind::Conquered => format!(
+            "{} {} was taken by {} from {}",
+            place,
+            change.village_name,
+            owner(&change.new_player),
+            owner(&change.old_player)
+        ),
+        WatchChangeKind::Population => format!(
+            "{} {} of {}: population {} → {}",
+            place,
+            change.village_name,
+            owner(&change.new_player),
+            population(change.old_population),
+            population(change.new_population)
+        ),
+        WatchChangeKind::Founded => format!(
+            "{} {} was founded by {} (population {})",
+            place,
+            change.village_name,
+            owner(&change.new_player),
+            population(change.new_population)
+        ),
+        WatchChangeKind::Abandoned => format!(
+            "{} {} of {} is gone",
+            place,
+            change.village_name,
+            owner(&change.old_player)
+        ),
+    }
+}